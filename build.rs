@@ -0,0 +1,53 @@
+// Serialize the embedded dictionaries into sorted tables at build time,
+// so that they are not parsed again whenever one is first selected.
+use std::{collections, env, fs, path};
+
+const DICTS: [&str; 3] = ["dict.txt", "dict.txt.small", "dict.txt.big"];
+
+fn main() {
+    let out_dir = path::PathBuf::from(env::var_os("OUT_DIR").expect("must be run by Cargo"));
+    for name in DICTS {
+        let in_path = path::Path::new("dicts").join(name);
+        println!("cargo::rerun-if-changed={path}", path = in_path.display());
+        let dict = fs::read(&in_path).expect("must have provided the embedded dictionaries");
+        fs::write(out_dir.join(format!("{name}.bin")), write_table(&dict))
+            .expect("must be able to write to `OUT_DIR`");
+    }
+}
+
+// Follow `check_dict` with the jieba format, where invalid lines are
+// skipped and duplicated words keep their first tag but take the last
+// frequency.  For each entry in order, the table holds the word and
+// the tag, each after its length as 4 bytes, then the frequency as 8
+// bytes, all little-endian.
+fn write_table(dict: &[u8]) -> Vec<u8> {
+    let mut words = collections::BTreeMap::new();
+    for line in dict.split(|&byte| byte == b'\n') {
+        let Ok(line) = str::from_utf8(line) else {
+            continue;
+        };
+        let mut fields = line.split_whitespace();
+        let Some(word) = fields.next() else {
+            continue;
+        };
+        let freq = match fields.next().map(str::parse::<u64>) {
+            Some(Ok(freq)) => freq,
+            Some(Err(_)) => continue,
+            None => 0,
+        };
+        words
+            .entry(word)
+            .and_modify(|(old_freq, _)| *old_freq = freq)
+            .or_insert((freq, fields.next().unwrap_or_default()));
+    }
+    let mut table = Vec::new();
+    for (word, (freq, tag)) in words {
+        for field in [word, tag] {
+            let len = u32::try_from(field.len()).expect("must have fields shorter than 4 GiB");
+            table.extend(len.to_le_bytes());
+            table.extend(field.as_bytes());
+        }
+        table.extend(freq.to_le_bytes());
+    }
+    table
+}
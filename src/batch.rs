@@ -0,0 +1,994 @@
+// Batch runs over many files, with their manifests, previews and
+// combined output.
+use crate::project::{escape_field, split_record, write_record};
+use crate::settings::{Layout, Settings};
+use crate::{
+    App, Operation, ROW_LEN, Result, Runner, Token, count_line_breaks, for_each_chunk,
+    get_file_name, quote_json, show_rows, split_rows, with_pick_file,
+};
+use eframe::egui;
+use rust_i18n::t;
+use std::io::{BufRead as _, Read as _, Write as _};
+use std::sync::mpsc;
+use std::{borrow, collections, error, fmt, fs, io, iter, num, ops, path, str, sync, thread, time};
+
+pub(crate) const COMBINE_FORMATS: [CombineFormat; 3] = [
+    CombineFormat::JsonLines,
+    CombineFormat::Tsv,
+    CombineFormat::Text,
+];
+#[derive(Copy, Clone, PartialEq)]
+pub(crate) enum CombineFormat {
+    JsonLines,
+    Tsv,
+    Text,
+}
+
+// What happened to each file of a batch run, in the order given.
+#[derive(Default)]
+pub(crate) struct BatchReport {
+    pub(crate) files: Vec<FileReport>,
+    elapsed: time::Duration,
+}
+
+// `size` and `modified` are as of just before the file was processed,
+// with `modified` in nanoseconds since the Unix epoch.
+pub(crate) struct FileReport {
+    path: path::PathBuf,
+    pub(crate) size: u64,
+    modified: u128,
+    elapsed: time::Duration,
+    pub(crate) error: Option<String>,
+}
+
+// Record of a batch run, written next to its output, enough to rerun
+// the files that failed or have changed since.  `output` is the output
+// folder, or the output file if `combine_format` is set.
+#[derive(Default)]
+pub(crate) struct Manifest {
+    pub(crate) operation: Operation,
+    pub(crate) settings: Settings,
+    pub(crate) combine_format: Option<CombineFormat>,
+    pub(crate) dict: String,
+    pub(crate) fingerprint: u64,
+    pub(crate) output: path::PathBuf,
+    pub(crate) report: BatchReport,
+}
+
+#[derive(Debug)]
+struct ManifestError(usize);
+
+// A batch running in the background, with its progress so far.
+pub(crate) struct RunningBatch {
+    what: String,
+    files: usize,
+    total: usize,
+    bytes: u64,
+    start: time::Instant,
+    events: mpsc::Receiver<BatchEvent>,
+    cancel: sync::Arc<sync::atomic::AtomicBool>,
+}
+
+pub(crate) enum BatchEvent {
+    // A file is done, with its size.
+    File(u64),
+    Done(Manifest, io::Result<()>),
+}
+
+// How the workers of a background batch report their progress and
+// learn that it is cancelled.
+pub(crate) struct BatchProgress {
+    pub(crate) events: mpsc::Sender<BatchEvent>,
+    pub(crate) cancel: sync::Arc<sync::atomic::AtomicBool>,
+}
+
+// Results of the first lines of each file of a batch, shown by rows
+// before anything is written, with what they were run with, so that
+// the whole files are run the same way.  The manifest has no output
+// yet.
+pub(crate) struct BatchPreview {
+    pub(crate) manifest: Manifest,
+    runner: Runner,
+    pub(crate) in_paths: Vec<path::PathBuf>,
+    pub(crate) output: String,
+    pub(crate) rows: Vec<ops::Range<usize>>,
+}
+
+impl App {
+    pub(crate) fn show_batch_options(&mut self, ui: &mut egui::Ui) {
+        ui.checkbox(&mut self.batch_mode, t!("batch-mode.text"))
+            .on_hover_text(t!("batch-mode.hover"));
+        ui.add(
+            egui::DragValue::new(&mut self.workers)
+                .range(0..=MAX_WORKERS)
+                .prefix(t!("workers.text")),
+        )
+        .on_hover_text(t!("workers.hover"));
+        let name = self.combine_format.map_or_else(
+            || String::from(t!("combine-format.none.name")),
+            CombineFormat::to_name,
+        );
+        egui::ComboBox::from_id_salt("combine format")
+            .selected_text(name)
+            .show_ui(ui, |ui| {
+                ui.selectable_value(
+                    &mut self.combine_format,
+                    None,
+                    t!("combine-format.none.name"),
+                );
+                for format in COMBINE_FORMATS {
+                    ui.selectable_value(&mut self.combine_format, Some(format), format.to_name());
+                }
+            })
+            .response
+            .on_hover_text(t!("combine-format.hover"));
+        ui.add(
+            egui::DragValue::new(&mut self.preview_lines)
+                .range(0..=MAX_PREVIEW_LINES)
+                .prefix(t!("preview-lines.text")),
+        )
+        .on_hover_text(t!("preview-lines.hover"));
+    }
+
+    pub(crate) fn show_batch_preview(&mut self, ui: &mut egui::Ui) {
+        let preview = self
+            .batch_preview
+            .as_ref()
+            .expect("cannot be `None`; must not show the preview without one");
+        let (mut write, mut discard) = (false, false);
+        ui.horizontal(|ui| {
+            let files = preview.in_paths.len();
+            ui.label(t!("batch-preview.text", files = files));
+            let enabled = self.running_batch.is_none();
+            write = ui
+                .add_enabled(enabled, egui::Button::new(t!("write-preview.text")))
+                .on_hover_text(t!("write-preview.hover"))
+                .clicked();
+            discard = ui
+                .button(t!("discard-preview.text"))
+                .on_hover_text(t!("discard-preview.hover"))
+                .clicked();
+        });
+        ui.separator();
+        show_rows(ui, &preview.output, &preview.rows);
+        if write {
+            self.write_preview();
+        } else if discard {
+            self.batch_preview = None;
+        }
+    }
+
+    // The progress of the running batch or preview, if any, else the
+    // stats of the last batch.
+    pub(crate) fn show_batch_status(&self, ui: &mut egui::Ui) {
+        if let Some((files, _)) = &self.previewing_batch {
+            ui.horizontal(|ui| {
+                // The spinner also keeps polling the preview.
+                ui.spinner();
+                ui.label(t!("batch-preview.running", files = files));
+            });
+        }
+        if let Some(batch) = &self.running_batch {
+            ui.horizontal(|ui| {
+                // The spinner also keeps polling the progress.
+                ui.spinner();
+                ui.label(batch.to_text());
+                if ui
+                    .button(t!("cancel-batch.text"))
+                    .on_hover_text(t!("cancel-batch.hover"))
+                    .clicked()
+                {
+                    batch.cancel.store(true, sync::atomic::Ordering::Relaxed);
+                }
+            });
+        } else if let Some(manifest) = &self.last_batch {
+            ui.label(manifest.report.to_text());
+        }
+    }
+
+    pub(crate) fn run_batch(&mut self, operation: Operation) {
+        if let Some(in_paths) = rfd::FileDialog::new().pick_files() {
+            self.start_batch(operation, in_paths);
+        }
+    }
+
+    // Nothing is written until the preview, if any, is accepted.  The
+    // preview reads every file, so it is rendered in the background.
+    pub(crate) fn start_batch(&mut self, operation: Operation, in_paths: Vec<path::PathBuf>) {
+        if self.preview_lines == 0 {
+            self.write_batch(operation, in_paths);
+            return;
+        }
+        let (manifest, runner) = (self.new_manifest(operation), self.runner(operation));
+        let (lines, workers) = (self.preview_lines, self.get_workers());
+        let files = in_paths.len();
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            let segmenter = runner.segmenter();
+            let func = |input: &str| segmenter.run_tokens(operation, input);
+            let layout = manifest.settings.to_layout(operation);
+            let output = preview_files(&in_paths, lines, &layout, workers, func);
+            let rows = split_rows(&output, ROW_LEN);
+            // The receiver is gone if another batch is started meanwhile.
+            let _ = sender.send(BatchPreview {
+                manifest,
+                runner,
+                in_paths,
+                output,
+                rows,
+            });
+        });
+        // Dropping several files starts a batch outside batch mode, but
+        // the preview is only shown in it.
+        self.batch_mode = true;
+        self.batch_preview = None;
+        self.previewing_batch = Some((files, receiver));
+    }
+
+    pub(crate) fn poll_batch_preview(&mut self) {
+        let Some((_, receiver)) = &self.previewing_batch else {
+            return;
+        };
+        match receiver.try_recv() {
+            Ok(preview) => self.batch_preview = Some(preview),
+            Err(mpsc::TryRecvError::Empty) => return,
+            Err(mpsc::TryRecvError::Disconnected) => {}
+        }
+        self.previewing_batch = None;
+    }
+
+    fn write_preview(&mut self) {
+        let preview = self
+            .batch_preview
+            .as_ref()
+            .expect("cannot be `None`; must not trigger this action without preview");
+        let Some(output) = pick_batch_output(preview.manifest.combine_format) else {
+            return;
+        };
+        let BatchPreview {
+            mut manifest,
+            runner,
+            in_paths,
+            ..
+        } = self
+            .batch_preview
+            .take()
+            .expect("cannot be `None`; must have checked the preview before");
+        manifest.output = output;
+        let what = manifest.operation.to_what();
+        self.run_manifest(manifest, runner, in_paths, false, &what);
+    }
+
+    fn write_batch(&mut self, operation: Operation, in_paths: Vec<path::PathBuf>) {
+        let Some(output) = pick_batch_output(self.combine_format) else {
+            return;
+        };
+        self.batch_preview = None;
+        self.previewing_batch = None;
+        let manifest = Manifest {
+            output,
+            ..self.new_manifest(operation)
+        };
+        let runner = self.runner(operation);
+        self.run_manifest(manifest, runner, in_paths, false, &operation.to_what());
+    }
+
+    // The manifest of a batch with the current settings and dictionary,
+    // without output yet.
+    fn new_manifest(&self, operation: Operation) -> Manifest {
+        let dict = self.dicts.selected_dict();
+        Manifest {
+            operation,
+            settings: self.settings(operation).clone(),
+            combine_format: self.combine_format,
+            dict: dict.name.to_name(),
+            fingerprint: dict.fingerprint(),
+            ..Default::default()
+        }
+    }
+
+    pub(crate) fn rerun_batch(&mut self) {
+        if let Err(err) = with_pick_file(|path| {
+            let file = fs::File::open(path)?;
+            let manifest = Manifest::read(&mut io::BufReader::new(file))?;
+            self.rerun_manifest(manifest);
+            Ok(())
+        }) {
+            self.open_error("rerun-batch.what", err);
+        }
+    }
+
+    // Rerun the files that failed or have changed, or every file if the
+    // selected dictionary differs or the output is combined.
+    pub(crate) fn rerun_manifest(&mut self, mut manifest: Manifest) {
+        let dict = self.dicts.selected_dict();
+        let fingerprint = dict.fingerprint();
+        let rerun_all = manifest.combine_format.is_some() || manifest.fingerprint != fingerprint;
+        let in_paths: Vec<_> = manifest
+            .report
+            .files
+            .iter()
+            .filter(|file| rerun_all || file.needs_rerun())
+            .map(|file| file.path.clone())
+            .collect();
+        manifest.dict = dict.name.to_name();
+        manifest.fingerprint = fingerprint;
+        let runner = Runner {
+            use_hmm: manifest.settings.use_hmm,
+            ..self.runner(manifest.operation)
+        };
+        self.run_manifest(manifest, runner, in_paths, true, "rerun-batch.what");
+    }
+
+    // Run `in_paths` with the settings of `manifest` in the background,
+    // reporting errors as `what`.  Batches are shown in batch mode only.
+    pub(crate) fn run_manifest(
+        &mut self,
+        mut manifest: Manifest,
+        runner: Runner,
+        in_paths: Vec<path::PathBuf>,
+        overwrite: bool,
+        what: &str,
+    ) {
+        let workers = self.get_workers();
+        let (sender, receiver) = mpsc::channel();
+        let cancel = sync::Arc::default();
+        let progress = BatchProgress {
+            events: sender,
+            cancel: sync::Arc::clone(&cancel),
+        };
+        self.batch_mode = true;
+        self.running_batch = Some(RunningBatch {
+            what: String::from(what),
+            files: 0,
+            total: in_paths.len(),
+            bytes: 0,
+            start: time::Instant::now(),
+            events: receiver,
+            cancel,
+        });
+        thread::spawn(move || {
+            let result = run_manifest_files(
+                &mut manifest,
+                &in_paths,
+                overwrite,
+                &runner,
+                workers,
+                &progress,
+            );
+            // The receiver is gone only if the app is closed meanwhile.
+            let _ = progress.events.send(BatchEvent::Done(manifest, result));
+        });
+    }
+
+    pub(crate) fn poll_batch(&mut self) {
+        let Some(batch) = &mut self.running_batch else {
+            return;
+        };
+        loop {
+            match batch.events.try_recv() {
+                Ok(BatchEvent::File(size)) => {
+                    batch.files += 1;
+                    batch.bytes += size;
+                }
+                Ok(BatchEvent::Done(manifest, result)) => {
+                    let what = batch.what.clone();
+                    let cancelled = batch.cancel.load(sync::atomic::Ordering::Relaxed);
+                    let err = match result {
+                        Ok(()) => manifest.report.first_error(),
+                        Err(err) => Some(err.into()),
+                    };
+                    self.running_batch = None;
+                    self.last_batch = Some(manifest);
+                    // The files left are reported as cancelled anyway.
+                    if let Some(err) = err
+                        && !cancelled
+                    {
+                        self.queue_error(&what, err);
+                    }
+                    return;
+                }
+                Err(mpsc::TryRecvError::Empty) => return,
+                Err(mpsc::TryRecvError::Disconnected) => {
+                    self.running_batch = None;
+                    return;
+                }
+            }
+        }
+    }
+
+    pub(crate) fn get_workers(&self) -> usize {
+        if self.workers == 0 {
+            thread::available_parallelism().map_or(1, num::NonZero::get)
+        } else {
+            self.workers
+        }
+    }
+}
+
+impl CombineFormat {
+    pub(crate) fn to_key(self) -> &'static str {
+        match self {
+            Self::JsonLines => "jsonl",
+            Self::Tsv => "tsv",
+            Self::Text => "text",
+        }
+    }
+
+    pub(crate) fn from_key(key: &str) -> Option<Self> {
+        COMBINE_FORMATS
+            .into_iter()
+            .find(|format| format.to_key() == key)
+    }
+
+    fn to_name(self) -> String {
+        let key = format!("combine-format.{key}.name", key = self.to_key());
+        String::from(t!(&key))
+    }
+
+    // Render the result of one file, named `name`, as a part of the
+    // combined file.  Line breaks are only kept in text.
+    pub(crate) fn render(self, name: &str, tokens: &[Token], layout: &Layout) -> String {
+        let items = tokens
+            .iter()
+            .filter(|token| count_line_breaks(&token.word).is_none())
+            .enumerate()
+            .map(|(index, token)| layout.render(token, index));
+        match self {
+            Self::JsonLines => {
+                let items: Vec<_> = items.map(|item| quote_json(&item)).collect();
+                format!(
+                    "{{\"file\":{name},\"items\":[{items}]}}\n",
+                    name = quote_json(name),
+                    items = items.join(","),
+                )
+            }
+            Self::Tsv => {
+                let name = escape_field(name);
+                let mut lines = String::new();
+                for item in items {
+                    lines.push_str(&name);
+                    lines.push('\t');
+                    lines.push_str(&escape_field(&item));
+                    lines.push('\n');
+                }
+                lines
+            }
+            Self::Text => {
+                let (output, _) = layout.join(tokens);
+                format!("==> {name} <==\n{output}\n\n")
+            }
+        }
+    }
+}
+
+impl BatchReport {
+    #[allow(clippy::cast_precision_loss)]
+    fn to_text(&self) -> String {
+        let (done, failed): (Vec<_>, Vec<_>) =
+            self.files.iter().partition(|file| file.error.is_none());
+        let bytes: u64 = done.iter().map(|file| file.size).sum();
+        let size = bytes as f64 / (1024.0 * 1024.0);
+        let secs = self.elapsed.as_secs_f64();
+        let mut text = String::from(t!(
+            "batch-stats.text",
+            files = done.len(),
+            size = format!("{size:.1}"),
+            secs = format!("{secs:.1}"),
+            rate = format!("{rate:.1}", rate = size / secs.max(f64::EPSILON)),
+        ));
+        if !failed.is_empty() {
+            text.push_str(&t!("batch-stats.failed", files = failed.len()));
+        }
+        text
+    }
+
+    pub(crate) fn first_error(&self) -> Option<Box<dyn error::Error>> {
+        self.files.iter().find_map(|file| {
+            let error = file.error.as_ref()?;
+            Some(format!("{path}: {error}", path = file.path.display()).into())
+        })
+    }
+
+    // Replace the reports of the files run again, keeping the others.
+    fn merge(&mut self, other: BatchReport) {
+        for file in other.files {
+            match self.files.iter_mut().find(|old| old.path == file.path) {
+                Some(old) => *old = file,
+                None => self.files.push(file),
+            }
+        }
+        self.elapsed = other.elapsed;
+    }
+}
+
+impl RunningBatch {
+    #[allow(clippy::cast_precision_loss)]
+    fn to_text(&self) -> String {
+        let size = self.bytes as f64 / (1024.0 * 1024.0);
+        let secs = self.start.elapsed().as_secs_f64();
+        String::from(t!(
+            "batch-progress.text",
+            files = self.files,
+            total = self.total,
+            rate = format!("{rate:.1}", rate = size / secs.max(f64::EPSILON)),
+        ))
+    }
+}
+
+impl BatchProgress {
+    fn is_cancelled(&self) -> bool {
+        self.cancel.load(sync::atomic::Ordering::Relaxed)
+    }
+
+    fn send_file(&self, file: &FileReport) {
+        // The receiver is gone only if the app is closed meanwhile.
+        let _ = self.events.send(BatchEvent::File(file.size));
+    }
+}
+
+impl FileReport {
+    fn cancelled(path: &path::Path) -> Self {
+        FileReport {
+            path: path.to_path_buf(),
+            size: 0,
+            modified: 0,
+            elapsed: time::Duration::ZERO,
+            error: Some(String::from(t!("batch-cancelled.text"))),
+        }
+    }
+
+    // Whether the file failed, or has changed or gone since it was run.
+    pub(crate) fn needs_rerun(&self) -> bool {
+        self.error.is_some() || stat_file(&self.path).ok() != Some((self.size, self.modified))
+    }
+}
+
+impl Manifest {
+    // Next to the output rather than in the output folder, where it
+    // could be mistaken for an output file.
+    pub(crate) fn to_path(&self) -> path::PathBuf {
+        let mut path = self.output.clone().into_os_string();
+        path.push(".manifest");
+        path.into()
+    }
+
+    pub(crate) fn read(buf: &mut impl io::BufRead) -> Result<Self> {
+        let mut manifest = Manifest::default();
+        let mut has_header = false;
+        for (idx, line) in buf.lines().enumerate() {
+            let line = line?;
+            let valid = split_record(&line).is_some_and(|record| {
+                let record: Vec<_> = record.iter().map(String::as_str).collect();
+                if idx == 0 {
+                    has_header = record == [MANIFEST_HEADER, MANIFEST_VERSION];
+                    has_header
+                } else {
+                    manifest.read_record(&record).is_some()
+                }
+            });
+            if !valid {
+                return Err(Box::new(ManifestError(idx + 1)));
+            }
+        }
+        if !has_header {
+            return Err(Box::new(ManifestError(1)));
+        }
+        Ok(manifest)
+    }
+
+    fn read_record(&mut self, record: &[&str]) -> Option<()> {
+        let parse_millis = |millis: &str| millis.parse().ok().map(time::Duration::from_millis);
+        match record {
+            ["operation", operation] => self.operation = Operation::from_key(operation)?,
+            ["template", template] => self.settings.template = String::from(*template),
+            ["separator", separator] => self.settings.separator = String::from(*separator),
+            ["line-separator", separator] => {
+                self.settings.line_separator = String::from(*separator);
+            }
+            ["use-hmm", use_hmm] => self.settings.use_hmm = use_hmm.parse().ok()?,
+            ["combine-format", ""] => self.combine_format = None,
+            ["combine-format", format] => {
+                self.combine_format = Some(CombineFormat::from_key(format)?);
+            }
+            ["dict", name, fingerprint] => {
+                self.dict = String::from(*name);
+                self.fingerprint = u64::from_str_radix(fingerprint, 16).ok()?;
+            }
+            ["output", output] => self.output = path::PathBuf::from(*output),
+            ["elapsed", millis] => self.report.elapsed = parse_millis(millis)?,
+            ["file", file_path, size, modified, millis, error @ ..] if error.len() <= 1 => {
+                self.report.files.push(FileReport {
+                    path: path::PathBuf::from(*file_path),
+                    size: size.parse().ok()?,
+                    modified: modified.parse().ok()?,
+                    elapsed: parse_millis(millis)?,
+                    error: error.first().map(|error| String::from(*error)),
+                });
+            }
+            _ => return None,
+        }
+        Some(())
+    }
+
+    pub(crate) fn write(&self, buf: &mut impl io::Write) -> io::Result<()> {
+        write_record(buf, &[MANIFEST_HEADER, MANIFEST_VERSION])?;
+        write_record(buf, &["operation", self.operation.to_key()])?;
+        let settings = &self.settings;
+        write_record(buf, &["template", &settings.template])?;
+        write_record(buf, &["separator", &settings.separator])?;
+        write_record(buf, &["line-separator", &settings.line_separator])?;
+        write_record(buf, &["use-hmm", &settings.use_hmm.to_string()])?;
+        let combine_format = self.combine_format.map_or("", CombineFormat::to_key);
+        write_record(buf, &["combine-format", combine_format])?;
+        let fingerprint = format!("{fingerprint:016x}", fingerprint = self.fingerprint);
+        write_record(buf, &["dict", &self.dict, &fingerprint])?;
+        write_record(buf, &["output", manifest_path(&self.output)?])?;
+        let elapsed = self.report.elapsed.as_millis().to_string();
+        write_record(buf, &["elapsed", &elapsed])?;
+        for file in &self.report.files {
+            let fields = [
+                file.size.to_string(),
+                file.modified.to_string(),
+                file.elapsed.as_millis().to_string(),
+            ];
+            let mut record = vec!["file", manifest_path(&file.path)?];
+            record.extend(fields.iter().map(String::as_str));
+            record.extend(file.error.as_deref());
+            write_record(buf, &record)?;
+        }
+        buf.flush()
+    }
+}
+
+impl fmt::Display for ManifestError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&t!("manifest.invalid-line", line = self.0))
+    }
+}
+
+impl error::Error for ManifestError {}
+
+const MANIFEST_HEADER: &str = "chissor-manifest";
+const MANIFEST_VERSION: &str = "2";
+
+pub(crate) fn manifest_path(path: &path::Path) -> io::Result<&str> {
+    path.to_str().ok_or_else(|| {
+        let text = t!("manifest.invalid-path", path = path.display());
+        io::Error::new(io::ErrorKind::InvalidData, String::from(text))
+    })
+}
+
+// The output folder, or the output file if combined.
+fn pick_batch_output(combine_format: Option<CombineFormat>) -> Option<path::PathBuf> {
+    match combine_format {
+        None => rfd::FileDialog::new().pick_folder(),
+        Some(_) => rfd::FileDialog::new().save_file(),
+    }
+}
+
+pub(crate) const MAX_WORKERS: usize = 256;
+pub(crate) const DEFAULT_PREVIEW_LINES: usize = 10;
+pub(crate) const MAX_PREVIEW_LINES: usize = 1000;
+// So that a file without line breaks is not read whole.
+pub(crate) const MAX_PREVIEW_LEN: usize = 64 * 1024;
+// Run `in_paths` with the settings of `manifest`, merge the results
+// into its report, and write it next to the output.
+fn run_manifest_files(
+    manifest: &mut Manifest,
+    in_paths: &[path::PathBuf],
+    overwrite: bool,
+    runner: &Runner,
+    workers: usize,
+    progress: &BatchProgress,
+) -> io::Result<()> {
+    // The manifest could not record the other paths.
+    for path in iter::once(&manifest.output).chain(in_paths) {
+        manifest_path(path)?;
+    }
+    let operation = manifest.operation;
+    let segmenter = runner.segmenter();
+    let func = |input: &str| segmenter.run_tokens(operation, input);
+    let layout = manifest.settings.to_layout(operation);
+    let report = match manifest.combine_format {
+        None => write_out_files(
+            in_paths,
+            &manifest.output,
+            &layout,
+            workers,
+            overwrite,
+            Some(progress),
+            func,
+        ),
+        Some(format) => write_combined_file(
+            in_paths,
+            &manifest.output,
+            format,
+            &layout,
+            workers,
+            Some(progress),
+            func,
+        )?,
+    };
+    manifest.report.merge(report);
+    let file = fs::File::create(manifest.to_path())?;
+    manifest.write(&mut io::BufWriter::new(file))
+}
+
+// Write the result of each file under `out_dir`, with up to `workers`
+// files at once, replacing existing files only if `overwrite`.  The
+// output files do not depend on the order of processing.
+pub(crate) fn write_out_files(
+    in_paths: &[path::PathBuf],
+    out_dir: &path::Path,
+    layout: &Layout,
+    workers: usize,
+    overwrite: bool,
+    progress: Option<&BatchProgress>,
+    func: impl Fn(&str) -> Vec<Token> + Sync,
+) -> BatchReport {
+    let start = time::Instant::now();
+    let results = run_files(in_paths, workers, progress, |in_path| {
+        let out_path = out_dir.join(
+            in_path
+                .file_name()
+                .expect("cannot be `None`; must be a regular file"),
+        );
+        let mut in_file = io::BufReader::new(fs::File::open(in_path)?);
+        let out_file = if overwrite {
+            fs::File::create(out_path)?
+        } else {
+            fs::File::create_new(out_path)?
+        };
+        let mut out_file = io::BufWriter::new(out_file);
+        write_chunked(&mut in_file, &mut out_file, layout, CHUNK_LEN, &func)
+    });
+    BatchReport {
+        files: results.into_iter().map(|(file, _)| file).collect(),
+        elapsed: start.elapsed(),
+    }
+}
+
+// Write the results of the files that succeed into `out_path` in the
+// order of `in_paths`, so that the output holds exactly the files that
+// the report has without error.  The results are streamed into a
+// temporary file next to `out_path`, which replaces it at the end, and
+// only those done before an earlier file are held meanwhile.
+pub(crate) fn write_combined_file(
+    in_paths: &[path::PathBuf],
+    out_path: &path::Path,
+    format: CombineFormat,
+    layout: &Layout,
+    workers: usize,
+    progress: Option<&BatchProgress>,
+    func: impl Fn(&str) -> Vec<Token> + Sync,
+) -> io::Result<BatchReport> {
+    let start = time::Instant::now();
+    let mut part_path = out_path.to_path_buf().into_os_string();
+    part_path.push(".part");
+    let part_path = path::PathBuf::from(part_path);
+    let mut out_file = io::BufWriter::new(fs::File::create(&part_path)?);
+    let mut files: Vec<_> = iter::repeat_with(|| None).take(in_paths.len()).collect();
+    let mut pending = collections::BTreeMap::new();
+    let mut next = 0;
+    let mut result = Ok(());
+    let run = |in_path: &path::Path| {
+        let in_file = fs::File::open(in_path)?;
+        let mut tokens = Vec::new();
+        for_each_chunk(
+            &mut io::BufReader::new(in_file),
+            CHUNK_LEN,
+            |chunk, base| {
+                tokens.extend(func(chunk).into_iter().map(|token| token.shift(base)));
+                Ok(())
+            },
+        )?;
+        Ok(format.render(&get_file_name(in_path), &tokens, layout))
+    };
+    for_each_file(in_paths, workers, progress, run, |idx, file, output| {
+        files[idx] = Some(file);
+        if result.is_err() {
+            return;
+        }
+        // A failed file is skipped, but still waited for in order.
+        pending.insert(idx, output);
+        while let Some(output) = pending.remove(&next) {
+            next += 1;
+            if let Some(output) = output
+                && let Err(err) = out_file.write_all(output.as_bytes())
+            {
+                result = Err(err);
+                pending.clear();
+                return;
+            }
+        }
+    });
+    let result = result.and_then(|()| out_file.flush());
+    drop(out_file);
+    if let Err(err) = result {
+        // The partial output is of no use.
+        let _ = fs::remove_file(&part_path);
+        return Err(err);
+    }
+    fs::rename(&part_path, out_path)?;
+    let files = files
+        .into_iter()
+        .map(|file| file.expect("cannot be `None`; must have run every file"))
+        .collect();
+    Ok(BatchReport {
+        files,
+        elapsed: start.elapsed(),
+    })
+}
+
+// Render the results of the first `lines` lines of each file, up to
+// `MAX_PREVIEW_LEN` bytes, as a combined text file would, with the
+// error in place of a failed file.
+fn preview_files(
+    in_paths: &[path::PathBuf],
+    lines: usize,
+    layout: &Layout,
+    workers: usize,
+    func: impl Fn(&str) -> Vec<Token> + Sync,
+) -> String {
+    let results = run_files(in_paths, workers, None, |in_path| {
+        let file = fs::File::open(in_path)?.take(MAX_PREVIEW_LEN as u64);
+        let mut reader = io::BufReader::new(file);
+        let mut buf = Vec::new();
+        for _ in 0..lines {
+            if reader.read_until(b'\n', &mut buf)? == 0 {
+                break;
+            }
+        }
+        // The limit may cut the last character short.
+        let input = match str::from_utf8(&buf) {
+            Ok(input) => input,
+            Err(err) if err.error_len().is_none() => str::from_utf8(&buf[..err.valid_up_to()])
+                .expect("cannot be `Err(_)`; must have checked the bytes before"),
+            Err(err) => return Err(io::Error::new(io::ErrorKind::InvalidData, err)),
+        };
+        let trimmed = input.trim_start();
+        let base = input[..input.len() - trimmed.len()].chars().count();
+        let tokens: Vec<_> = func(trimmed.trim_end())
+            .into_iter()
+            .map(|token| token.shift(base))
+            .collect();
+        Ok(CombineFormat::Text.render(&get_file_name(in_path), &tokens, layout))
+    });
+    // Errors are shown as is, whatever the template.
+    let layout = Layout {
+        template: borrow::Cow::Borrowed("{word}"),
+        ..layout.clone()
+    };
+    results
+        .into_iter()
+        .map(|(file, output)| {
+            output.unwrap_or_else(|| {
+                let tokens = [Token {
+                    word: file.error.unwrap_or_default(),
+                    tag: String::new(),
+                    input: 0..0,
+                }];
+                CombineFormat::Text.render(&get_file_name(&file.path), &tokens, &layout)
+            })
+        })
+        .collect()
+}
+
+// Run `func` on each of `in_paths` with up to `workers` files at once,
+// and return the reports and results in the order of `in_paths`.
+fn run_files<T: Send>(
+    in_paths: &[path::PathBuf],
+    workers: usize,
+    progress: Option<&BatchProgress>,
+    func: impl Fn(&path::Path) -> io::Result<T> + Sync,
+) -> Vec<(FileReport, Option<T>)> {
+    let mut results: Vec<_> = iter::repeat_with(|| None).take(in_paths.len()).collect();
+    for_each_file(in_paths, workers, progress, func, |idx, file, result| {
+        results[idx] = Some((file, result));
+    });
+    results
+        .into_iter()
+        .map(|result| result.expect("cannot be `None`; must have run every file"))
+        .collect()
+}
+
+// Run `func` on each of `in_paths` with up to `workers` files at once,
+// and pass each report and result to `sink` as soon as it is done, with
+// its index in `in_paths`.  Once `progress` is cancelled, the files left
+// fail without running.
+fn for_each_file<T: Send>(
+    in_paths: &[path::PathBuf],
+    workers: usize,
+    progress: Option<&BatchProgress>,
+    func: impl Fn(&path::Path) -> io::Result<T> + Sync,
+    mut sink: impl FnMut(usize, FileReport, Option<T>),
+) {
+    let next = sync::atomic::AtomicUsize::new(0);
+    let is_cancelled = || progress.is_some_and(BatchProgress::is_cancelled);
+    thread::scope(|scope| {
+        let (sender, receiver) = mpsc::channel();
+        for _ in 0..workers.min(in_paths.len()).max(1) {
+            let sender = sender.clone();
+            let (next, func, is_cancelled) = (&next, &func, &is_cancelled);
+            scope.spawn(move || {
+                loop {
+                    let idx = next.fetch_add(1, sync::atomic::Ordering::Relaxed);
+                    let Some(in_path) = in_paths.get(idx) else {
+                        return;
+                    };
+                    let result = if is_cancelled() {
+                        (FileReport::cancelled(in_path), None)
+                    } else {
+                        run_file(in_path, func)
+                    };
+                    // The receiver lives as long as the scope.
+                    let _ = sender.send((idx, result));
+                }
+            });
+        }
+        drop(sender);
+        for (idx, (file, result)) in receiver {
+            if let Some(progress) = progress {
+                progress.send_file(&file);
+            }
+            sink(idx, file, result);
+        }
+    });
+}
+
+// The file is stated before `func` runs, so that a change while it
+// runs is caught by the next rerun.
+fn run_file<T>(
+    path: &path::Path,
+    func: &impl Fn(&path::Path) -> io::Result<T>,
+) -> (FileReport, Option<T>) {
+    let (size, modified) = stat_file(path).unwrap_or_default();
+    let start = time::Instant::now();
+    let result = func(path);
+    let report = FileReport {
+        path: path.to_path_buf(),
+        size,
+        modified,
+        elapsed: start.elapsed(),
+        error: result.as_ref().err().map(ToString::to_string),
+    };
+    (report, result.ok())
+}
+
+fn stat_file(path: &path::Path) -> io::Result<(u64, u128)> {
+    let metadata = fs::metadata(path)?;
+    let modified = metadata.modified()?.duration_since(time::UNIX_EPOCH);
+    Ok((metadata.len(), modified.unwrap_or_default().as_nanos()))
+}
+
+const CHUNK_LEN: usize = 64 * 1024;
+// Write the result of `in_buf` as `Layout::join` would, where line
+// breaks, offsets and indices are carried over between chunks.
+pub(crate) fn write_chunked(
+    in_buf: &mut impl io::BufRead,
+    out_buf: &mut impl io::Write,
+    layout: &Layout,
+    len: usize,
+    mut func: impl FnMut(&str) -> Vec<Token>,
+) -> io::Result<()> {
+    let mut breaks = None;
+    let mut index = 0;
+    for_each_chunk(in_buf, len, |chunk, base| {
+        for token in func(chunk) {
+            if let Some(count) = count_line_breaks(&token.word) {
+                breaks = breaks.map(|breaks| breaks + count);
+                continue;
+            }
+            if let Some(breaks) = breaks {
+                out_buf.write_all(layout.to_separator(breaks).as_bytes())?;
+            }
+            out_buf.write_all(layout.render(&token.shift(base), index).as_bytes())?;
+            index += 1;
+            breaks = Some(0);
+        }
+        Ok(())
+    })?;
+    writeln!(out_buf)?;
+    out_buf.flush()
+}
@@ -0,0 +1,433 @@
+// Hidden Markov models for runs of single characters not in the
+// dictionary, as loaded, trained and written in jieba's format.
+use crate::{App, Result, get_file_name, has_han, is_han, with_pick_file, with_save_file};
+use eframe::egui;
+use jieba_rs as jieba;
+use rust_i18n::t;
+use std::io::BufRead as _;
+use std::sync::mpsc;
+use std::{array, collections, error, fmt, fs, io, iter, thread};
+
+// Log probabilities of an HMM over the states of `HMM_STATES`, used
+// instead of the one built into jieba.
+pub(crate) struct Hmm {
+    pub(crate) name: String,
+    pub(crate) start: [f64; 4],
+    pub(crate) trans: [[f64; 4]; 4],
+    pub(crate) emit: [collections::BTreeMap<char, f64>; 4],
+}
+
+#[derive(Debug)]
+enum HmmError {
+    InvalidLine(usize),
+    Incomplete,
+    EmptyCorpus,
+}
+
+impl App {
+    pub(crate) fn show_hmm_actions(&mut self, ui: &mut egui::Ui) {
+        let name = self
+            .dicts
+            .selected_dict()
+            .hmm
+            .as_ref()
+            .map_or_else(|| String::from(t!("hmm.builtin")), |hmm| hmm.name.clone());
+        ui.label(t!("hmm.text", name = name))
+            .on_hover_text(t!("hmm.hover"));
+        if ui
+            .button(t!("load-hmm.text"))
+            .on_hover_text(t!("load-hmm.hover"))
+            .clicked()
+        {
+            self.load_hmm();
+        }
+        if self.training_hmm.is_some() {
+            // The spinner also keeps polling the result.
+            ui.spinner().on_hover_text(t!("training-hmm.hover"));
+        } else if ui
+            .button(t!("train-hmm.text"))
+            .on_hover_text(t!("train-hmm.hover"))
+            .clicked()
+        {
+            self.train_hmm();
+        }
+        if ui
+            .add_enabled(
+                self.can_export_hmm(),
+                egui::Button::new(t!("export-hmm.text")),
+            )
+            .on_hover_text(t!("export-hmm.hover"))
+            .clicked()
+        {
+            self.export_hmm();
+        }
+        if ui
+            .add_enabled(
+                self.dicts.can_reset_hmm(),
+                egui::Button::new(t!("reset-hmm.text")),
+            )
+            .on_hover_text(t!("reset-hmm.hover"))
+            .clicked()
+        {
+            self.dicts.reset_hmm();
+        }
+    }
+
+    pub(crate) fn load_hmm(&mut self) {
+        if let Err(err) = with_pick_file(|path| {
+            let file = fs::File::open(&path)?;
+            let hmm = Hmm::read(get_file_name(&path), &mut io::BufReader::new(file))?;
+            self.dicts.load_hmm(self.dicts.idx, "history.load-hmm", hmm);
+            Ok(())
+        }) {
+            self.open_error("load-hmm.what", err);
+        }
+    }
+
+    // Training reads the whole corpus, so it runs in the background,
+    // for the dictionary selected now.
+    pub(crate) fn train_hmm(&mut self) {
+        let Some(path) = rfd::FileDialog::new().pick_file() else {
+            return;
+        };
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            let result = fs::File::open(&path)
+                .map_err(Into::into)
+                .and_then(|file| Hmm::train(get_file_name(&path), &mut io::BufReader::new(file)));
+            // The receiver is gone only if the app is closed meanwhile.
+            let _ = sender.send(result.map_err(|err| err.to_string()));
+        });
+        self.training_hmm = Some((self.dicts.ids[self.dicts.idx], receiver));
+    }
+
+    pub(crate) fn poll_training_hmm(&mut self) {
+        let Some((id, receiver)) = &self.training_hmm else {
+            return;
+        };
+        let id = *id;
+        let result = match receiver.try_recv() {
+            Ok(result) => result,
+            Err(mpsc::TryRecvError::Empty) => return,
+            Err(mpsc::TryRecvError::Disconnected) => {
+                self.training_hmm = None;
+                return;
+            }
+        };
+        self.training_hmm = None;
+        match result {
+            // The dictionary may have been removed meanwhile.
+            Ok(hmm) => {
+                if let Some(idx) = self.dicts.position(id) {
+                    self.dicts.load_hmm(idx, "history.train-hmm", hmm);
+                }
+            }
+            Err(err) => self.queue_error("train-hmm.what", err.into()),
+        }
+    }
+
+    pub(crate) fn can_export_hmm(&self) -> bool {
+        self.dicts.selected_dict().hmm.is_some()
+    }
+
+    pub(crate) fn export_hmm(&mut self) {
+        let hmm = self
+            .dicts
+            .selected_dict()
+            .hmm
+            .as_ref()
+            .expect("cannot be `None`; must not trigger this action for built-in HMM");
+        if let Err(err) = with_save_file(|path| {
+            hmm.write(&mut io::BufWriter::new(fs::File::create(path)?))?;
+            Ok(())
+        }) {
+            self.open_error("export-hmm.what", err);
+        }
+    }
+}
+
+impl Hmm {
+    // Comments aside, the file has a line of start probabilities, then
+    // a line of transitions and a line of emissions as `char:prob,…`
+    // from each state, as jieba's `hmm.model`.  Blank lines are skipped
+    // except as empty emissions.
+    pub(crate) fn read(name: impl Into<String>, buf: &mut impl io::BufRead) -> Result<Self> {
+        let mut hmm = Hmm {
+            name: name.into(),
+            start: [0.0; 4],
+            trans: [[0.0; 4]; 4],
+            emit: Default::default(),
+        };
+        let mut row = 0;
+        for (idx, line) in buf.lines().enumerate() {
+            let line = line?;
+            let line = line.trim();
+            if line.starts_with('#') || (line.is_empty() && !(5..9).contains(&row)) {
+                continue;
+            }
+            if hmm.read_row(row, line).is_none() {
+                return Err(Box::new(HmmError::InvalidLine(idx + 1)));
+            }
+            row += 1;
+        }
+        if row != 9 {
+            return Err(Box::new(HmmError::Incomplete));
+        }
+        Ok(hmm)
+    }
+
+    // Count the states of each word of Han characters in the corpus, cut
+    // by whitespace, where other words break the sequence as line ends
+    // do, and take the relative frequencies.  Words may be tagged as
+    // `word/tag`, and compounds bracketed as `[word/tag word/tag]tag`,
+    // as in the People's Daily corpus.
+    pub(crate) fn train(name: impl Into<String>, buf: &mut impl io::BufRead) -> Result<Self> {
+        let mut start = [0; 4];
+        let mut trans = [[0; 4]; 4];
+        let mut emit: [collections::BTreeMap<char, u64>; 4] = Default::default();
+        for line in buf.lines() {
+            let line = line?;
+            let mut prev: Option<usize> = None;
+            for word in line.split_whitespace() {
+                let word = word.trim_start_matches('[');
+                let word = word.split_once('/').map_or(word, |(word, _)| word);
+                if word.is_empty() || !word.chars().all(is_han) {
+                    prev = None;
+                    continue;
+                }
+                let len = word.chars().count();
+                for (idx, c) in word.chars().enumerate() {
+                    let state = match idx {
+                        _ if len == 1 => HMM_SINGLE,
+                        0 => HMM_BEGIN,
+                        _ if idx == len - 1 => HMM_END,
+                        _ => HMM_MIDDLE,
+                    };
+                    match prev {
+                        None => start[state] += 1,
+                        Some(prev) => trans[prev][state] += 1,
+                    }
+                    *emit[state].entry(c).or_default() += 1;
+                    prev = Some(state);
+                }
+            }
+        }
+        if start.iter().all(|&count| count == 0) {
+            return Err(Box::new(HmmError::EmptyCorpus));
+        }
+        Ok(Hmm {
+            name: name.into(),
+            start: to_log_probs(start),
+            trans: trans.map(to_log_probs),
+            emit: emit.map(|counts| {
+                let total = counts.values().sum();
+                counts
+                    .into_iter()
+                    .map(|(c, count)| (c, to_log_prob(count, total)))
+                    .collect()
+            }),
+        })
+    }
+
+    fn read_row(&mut self, row: usize, line: &str) -> Option<()> {
+        match row {
+            0 => self.start = parse_log_probs(line)?,
+            1..=4 => self.trans[row - 1] = parse_log_probs(line)?,
+            5..=8 => {
+                for entry in line.split(',').filter(|entry| !entry.is_empty()) {
+                    let (c, prob) = entry.trim().rsplit_once(':')?;
+                    let mut chars = c.chars();
+                    let (Some(c), None) = (chars.next(), chars.next()) else {
+                        return None;
+                    };
+                    self.emit[row - 5].insert(c, parse_log_prob(prob)?);
+                }
+            }
+            _ => return None,
+        }
+        Some(())
+    }
+
+    pub(crate) fn write(&self, buf: &mut impl io::Write) -> io::Result<()> {
+        writeln!(buf, "#{states}", states = HMM_STATES.join(","))?;
+        writeln!(buf, "#prob_start")?;
+        write_log_probs(buf, &self.start)?;
+        writeln!(buf, "#prob_trans")?;
+        for (state, probs) in iter::zip(HMM_STATES, &self.trans) {
+            writeln!(buf, "#{state}")?;
+            write_log_probs(buf, probs)?;
+        }
+        writeln!(buf, "#prob_emit")?;
+        for (state, emit) in iter::zip(HMM_STATES, &self.emit) {
+            writeln!(buf, "#{state}")?;
+            let entries: Vec<_> = emit
+                .iter()
+                .map(|(c, prob)| format!("{c}:{prob:?}"))
+                .collect();
+            writeln!(buf, "{entries}", entries = entries.join(","))?;
+        }
+        buf.flush()
+    }
+
+    pub(crate) fn to_text(&self) -> String {
+        let mut buf = Vec::new();
+        self.write(&mut buf)
+            .expect("cannot be `Err`; writing to `Vec` never fails");
+        String::from_utf8(buf).expect("cannot be `Err`; must have written UTF-8")
+    }
+
+    // Cut as `jieba::Jieba::cut` does with HMM, except that runs of
+    // single Han characters are cut by this model instead.
+    pub(crate) fn cut_tokens<'b>(
+        &self,
+        jieba: &jieba::Jieba,
+        input: &'b str,
+    ) -> Vec<jieba::Token<'b>> {
+        let mut tokens = Vec::new();
+        // Byte range and first character index of the current run.
+        let mut run = 0..0;
+        let mut run_start = 0;
+        for token in jieba.cut(input, false) {
+            if token.end - token.start == 1 && has_han(token.word) {
+                if run.is_empty() {
+                    run_start = token.start;
+                }
+                run.end += token.word.len();
+            } else {
+                self.cut_run(jieba, &input[run.clone()], run_start, &mut tokens);
+                run = run.end + token.word.len()..run.end + token.word.len();
+                tokens.push(token);
+            }
+        }
+        self.cut_run(jieba, &input[run], run_start, &mut tokens);
+        tokens
+    }
+
+    // A run that is a word by itself is kept as single characters, as
+    // jieba does.
+    fn cut_run<'b>(
+        &self,
+        jieba: &jieba::Jieba,
+        run: &'b str,
+        mut start: usize,
+        tokens: &mut Vec<jieba::Token<'b>>,
+    ) {
+        let words: Vec<_> = if run.chars().nth(1).is_none() || jieba.has_word(run) {
+            run.char_indices()
+                .map(|(idx, c)| &run[idx..idx + c.len_utf8()])
+                .collect()
+        } else {
+            self.cut(run)
+        };
+        for word in words {
+            let end = start + word.chars().count();
+            tokens.push(jieba::Token { word, start, end });
+            start = end;
+        }
+    }
+
+    // Find the most likely states by Viterbi, where a word ends at each
+    // `End` or `Single`.
+    pub(crate) fn cut<'b>(&self, text: &'b str) -> Vec<&'b str> {
+        let emit = |state: usize, c| self.emit[state].get(&c).copied().unwrap_or(MIN_LOG_PROB);
+        let chars: Vec<_> = text.char_indices().collect();
+        let Some(&(_, first)) = chars.first() else {
+            return Vec::new();
+        };
+        let mut probs = vec![array::from_fn::<_, 4, _>(|state| {
+            self.start[state] + emit(state, first)
+        })];
+        let mut prevs = vec![[0; 4]];
+        for &(_, c) in &chars[1..] {
+            let last = probs.last().expect("cannot be `None`; must have first");
+            let (mut prob, mut prev) = ([0.0; 4], [0; 4]);
+            for (state, froms) in HMM_PREVS.iter().enumerate() {
+                let (from_prob, from) = froms
+                    .iter()
+                    .map(|&from| (last[from] + self.trans[from][state], from))
+                    .max_by(|(a, _), (b, _)| a.total_cmp(b))
+                    .expect("cannot be `None`; must have previous states");
+                prob[state] = from_prob + emit(state, c);
+                prev[state] = from;
+            }
+            probs.push(prob);
+            prevs.push(prev);
+        }
+        let last = probs.last().expect("cannot be `None`; must have first");
+        let mut state = if last[HMM_END] >= last[HMM_SINGLE] {
+            HMM_END
+        } else {
+            HMM_SINGLE
+        };
+        let mut states = vec![0; chars.len()];
+        for idx in (0..chars.len()).rev() {
+            states[idx] = state;
+            state = prevs[idx][state];
+        }
+        let mut words = Vec::new();
+        let mut start = 0;
+        for (&(idx, c), state) in iter::zip(&chars, states) {
+            if state == HMM_END || state == HMM_SINGLE {
+                let end = idx + c.len_utf8();
+                words.push(&text[start..end]);
+                start = end;
+            }
+        }
+        words
+    }
+}
+
+impl fmt::Display for HmmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let text = match self {
+            Self::InvalidLine(line) => t!("hmm.invalid-line", line = line),
+            Self::Incomplete => t!("hmm.incomplete"),
+            Self::EmptyCorpus => t!("hmm.empty-corpus"),
+        };
+        f.write_str(&text)
+    }
+}
+
+impl error::Error for HmmError {}
+
+// States in the order of jieba, each with the states that may precede it.
+const HMM_STATES: [&str; 4] = ["Begin", "End", "Middle", "Single"];
+const HMM_PREVS: [[usize; 2]; 4] = [[1, 3], [0, 2], [2, 0], [3, 1]];
+pub(crate) const HMM_BEGIN: usize = 0;
+pub(crate) const HMM_END: usize = 1;
+pub(crate) const HMM_MIDDLE: usize = 2;
+pub(crate) const HMM_SINGLE: usize = 3;
+pub(crate) const MIN_LOG_PROB: f64 = -3.14e100;
+
+fn parse_log_probs(line: &str) -> Option<[f64; 4]> {
+    let probs: Vec<_> = line
+        .split_whitespace()
+        .map(parse_log_prob)
+        .collect::<Option<_>>()?;
+    probs.try_into().ok()
+}
+
+fn parse_log_prob(text: &str) -> Option<f64> {
+    let prob: f64 = text.trim().parse().ok()?;
+    (prob.is_finite() && prob <= 0.0).then_some(prob)
+}
+
+fn to_log_probs(counts: [u64; 4]) -> [f64; 4] {
+    let total = counts.iter().sum();
+    counts.map(|count| to_log_prob(count, total))
+}
+
+#[allow(clippy::cast_precision_loss)]
+fn to_log_prob(count: u64, total: u64) -> f64 {
+    if count == 0 {
+        MIN_LOG_PROB
+    } else {
+        (count as f64 / total as f64).ln()
+    }
+}
+
+// `Debug` keeps exponents, which `Display` writes out in full.
+fn write_log_probs(buf: &mut impl io::Write, probs: &[f64; 4]) -> io::Result<()> {
+    let probs: Vec<_> = probs.iter().map(|prob| format!("{prob:?}")).collect();
+    writeln!(buf, "{probs}", probs = probs.join(" "))
+}
@@ -10,16 +10,41 @@ program.  If not, see <https://spdx.org/licenses/MIT.html>.  */
 
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 #![warn(clippy::pedantic)]
+use crate::batch::{BatchPreview, CombineFormat, DEFAULT_PREVIEW_LINES, Manifest, RunningBatch};
+use crate::hmm::Hmm;
+use crate::project::{PREFERENCES_KEY, write_entries, write_record};
+use crate::server::serve;
+use crate::settings::{Preset, Settings};
 use eframe::egui;
 use rust_i18n::t;
-use std::io::Write as _;
-use std::{error, fs, io, path, process, result, sync};
+use std::io::{BufRead as _, Read as _, Write as _};
+use std::sync::mpsc;
+use std::{
+    borrow, cmp, collections, env, error, fmt, fs, io, iter, mem, ops, path, process, result, str,
+    sync, thread, time,
+};
 use {jieba_rs as jieba, rust_i18n as i18n};
 
+mod batch;
+mod hmm;
+mod project;
+mod server;
+mod settings;
+
 i18n::i18n!("locales");
 
 const WINDOW_TITLE: &str = "Chissor";
 fn main() {
+    let mut args = env::args().skip(1);
+    // Other arguments, such as a file opened with the program, are left
+    // to the GUI.
+    if args.next().as_deref() == Some("serve") {
+        if let Err(err) = serve(args) {
+            eprintln!("Serving failed: {err}");
+            process::exit(1);
+        }
+        return;
+    }
     let options = eframe::NativeOptions::default();
     if let Err(err) = eframe::run_native(
         WINDOW_TITLE,
@@ -37,15 +62,38 @@ type Result<T> = result::Result<T, Box<dyn error::Error>>;
 struct App {
     locale: Locale,
     dicts: Dicts,
+    dict_name: String,
+    format: Format,
     word: String,
     freq: String,
     tag: String,
-    input: String,
-    output: String,
-    separator: String,
-    use_hmm: bool,
+    docs: Docs,
+    settings: [Settings; OPERATIONS.len()],
+    settings_operation: Operation,
+    presets: Vec<Preset>,
+    preset_name: String,
     batch_mode: bool,
+    workers: usize,
+    combine_format: Option<CombineFormat>,
+    preview_lines: usize,
+    batch_preview: Option<BatchPreview>,
+    // The number of files being previewed in the background, and the
+    // preview.
+    previewing_batch: Option<(usize, mpsc::Receiver<BatchPreview>)>,
+    running_batch: Option<RunningBatch>,
+    // The id of the dictionary to train for, and the result.
+    training_hmm: Option<(usize, mpsc::Receiver<result::Result<Hmm, String>>)>,
+    last_batch: Option<Manifest>,
+    clipboard: Clipboard,
+    focus: Option<usize>,
+    shortcuts: Shortcuts,
+    palette: Option<CommandPalette>,
+    check_dialog: Option<CheckDialog>,
+    checks: collections::VecDeque<(path::PathBuf, CheckKind)>,
+    drop_pos: Option<egui::Pos2>,
     error_dialog: Option<ErrorDialog>,
+    // Errors from background work that finished while a dialog was open.
+    errors: collections::VecDeque<ErrorDialog>,
 }
 
 const LOCALES: [Locale; 3] = [Locale::En, Locale::ZhCn, Locale::ZhHk];
@@ -67,36 +115,389 @@ enum Theme {
 }
 
 // Invariants:
-//  - `idx` must be between `0..dicts.len()`;
-//  - `dicts` must be nonempty.
+//  - `idx` must be between `0..docs.len()`;
+//  - `docs` must be nonempty.
+struct Docs {
+    idx: usize,
+    docs: Vec<Doc>,
+}
+
+#[derive(Default)]
+struct Doc {
+    name: Option<String>,
+    input: String,
+    output: String,
+    items: Vec<Item>,
+    edited: bool,
+    rows: Vec<ops::Range<usize>>,
+    dict: usize,
+    operation: Operation,
+    // The output and items of large input, run in the background.
+    running: Option<mpsc::Receiver<(String, Vec<Item>)>>,
+}
+
+// A result item, with the character ranges where it comes from in the
+// input and where it goes in the output.
+#[derive(Clone, Debug, PartialEq)]
+struct Item {
+    text: String,
+    input: ops::Range<usize>,
+    output: ops::Range<usize>,
+}
+
+// A word cut from the input, before it is rendered into an item.  `tag`
+// is empty unless the operation tags words.
+struct Token {
+    word: String,
+    tag: String,
+    input: ops::Range<usize>,
+}
+
+const COPY_FORMATS: [CopyFormat; 3] = [CopyFormat::Text, CopyFormat::Lines, CopyFormat::Json];
+#[derive(Copy, Clone, PartialEq)]
+enum CopyFormat {
+    Text,
+    Lines,
+    Json,
+}
+
+// The system clipboard, opened on first use.
+#[derive(Default)]
+struct Clipboard {
+    inner: Option<arboard::Clipboard>,
+    watch: bool,
+    watcher: Option<ClipboardWatcher>,
+    copied: Option<String>,
+}
+
+// A background thread reading the clipboard, which ends once dropped.
+struct ClipboardWatcher {
+    texts: mpsc::Receiver<String>,
+    stop: mpsc::Sender<()>,
+}
+
+const OPERATIONS: [Operation; 4] = [
+    Operation::Segment,
+    Operation::SegmentGranular,
+    Operation::Search,
+    Operation::Tag,
+];
+#[derive(Default, Copy, Clone, PartialEq)]
+enum Operation {
+    #[default]
+    Segment,
+    SegmentGranular,
+    Search,
+    Tag,
+}
+
+const COMMANDS: [Command; 36] = [
+    Command::Palette,
+    Command::OpenProject,
+    Command::SaveProject,
+    Command::UseHmm,
+    Command::BatchMode,
+    Command::NewDict,
+    Command::LoadDict,
+    Command::ExportDict,
+    Command::RemoveDict,
+    Command::RenameDict,
+    Command::DuplicateDict,
+    Command::MoveUpDict,
+    Command::MoveDownDict,
+    Command::LoadHmm,
+    Command::TrainHmm,
+    Command::ExportHmm,
+    Command::ResetHmm,
+    Command::AddWord,
+    Command::DeleteWord,
+    Command::DisableWord,
+    Command::Undo,
+    Command::Redo,
+    Command::NewDoc,
+    Command::CloseDoc,
+    Command::Import,
+    Command::PasteAndRun,
+    Command::WatchClipboard,
+    Command::Export,
+    Command::RerunBatch,
+    Command::Copy(CopyFormat::Text),
+    Command::Copy(CopyFormat::Lines),
+    Command::Copy(CopyFormat::Json),
+    Command::Run(Operation::Segment),
+    Command::Run(Operation::SegmentGranular),
+    Command::Run(Operation::Search),
+    Command::Run(Operation::Tag),
+];
+#[derive(Copy, Clone, PartialEq)]
+enum Command {
+    Palette,
+    OpenProject,
+    SaveProject,
+    UseHmm,
+    BatchMode,
+    NewDict,
+    LoadDict,
+    ExportDict,
+    RemoveDict,
+    RenameDict,
+    DuplicateDict,
+    MoveUpDict,
+    MoveDownDict,
+    LoadHmm,
+    TrainHmm,
+    ExportHmm,
+    ResetHmm,
+    AddWord,
+    DeleteWord,
+    DisableWord,
+    Undo,
+    Redo,
+    NewDoc,
+    CloseDoc,
+    Import,
+    PasteAndRun,
+    WatchClipboard,
+    Export,
+    RerunBatch,
+    Copy(CopyFormat),
+    Run(Operation),
+}
+
+// Invariants:
+//  - each command and each shortcut must be bound at most once;
+//  - `bindings` must be sorted from the most modifiers to the least,
+//    since extra modifiers are ignored when matching.
+struct Shortcuts {
+    bindings: Vec<(Command, egui::KeyboardShortcut)>,
+}
+
+struct CommandPalette {
+    id: egui::Id,
+    query: String,
+    focus: bool,
+    recording: Option<Command>,
+}
+
+const HISTORY_LIMIT: usize = 100;
+// Invariants:
+//  - `idx` must be between `0..slots.len()`;
+//  - `slots` must be nonempty;
+//  - `slots[idx]` must be loaded;
+//  - `pending`, if any, must be between `0..slots.len()`;
+//  - `ids` must be unique, below `next_id`, and as long as `slots`;
+//  - `history` must be undone, and `future` redone, in reverse order.
 struct Dicts {
     idx: usize,
-    dicts: Vec<Dict>,
+    pending: Option<usize>,
+    slots: Vec<Slot>,
+    ids: Vec<usize>,
+    next_id: usize,
+    history: collections::VecDeque<Record>,
+    future: Vec<Record>,
+}
+
+// A change to the dictionaries described by `t!(key, name = arg)`.
+struct Record {
+    key: &'static str,
+    arg: String,
+    change: Change,
+}
+
+// Changes refer to dictionaries by position, which is only valid
+// because the history is linear.
+enum Change {
+    Edit {
+        idx: usize,
+        delta: Delta,
+    },
+    Insert {
+        idx: usize,
+        id: usize,
+        dict: Snapshot,
+    },
+    Remove {
+        idx: usize,
+        id: usize,
+        dict: Snapshot,
+    },
+    Swap {
+        idx: usize,
+        other: usize,
+    },
+}
+
+// An edit to a dictionary, which returns its inverse when applied.
+// Edits that only add words update `jieba::Jieba` in place.
+enum Delta {
+    Word(String, WordState),
+    InsertLayer(usize, Layer, collections::BTreeSet<String>),
+    RemoveLayer(usize),
+    ToggleLayer(usize),
+    Rename(DictName),
+    Hmm(Option<sync::Arc<Hmm>>),
+}
+
+// The entries of a word in each source of a dictionary.
+#[derive(Default, PartialEq)]
+struct WordState {
+    base: Option<Entry>,
+    layers: Vec<Option<Entry>>,
+    words: Option<Entry>,
+    disabled: bool,
 }
 
+// A dictionary without its `jieba::Jieba`, which is expensive to keep
+// around and is rebuilt when restored.
+#[derive(Clone)]
+struct Snapshot {
+    name: DictName,
+    base: sync::Arc<Words>,
+    layers: Vec<Layer>,
+    words: Words,
+    disabled: collections::BTreeSet<String>,
+    hmm: Option<sync::Arc<Hmm>>,
+}
+
+// Embedded dictionaries are only loaded when first selected.
+enum Slot {
+    Loaded(Dict),
+    Unloaded(Embedded),
+    Loading(Embedded, mpsc::Receiver<Dict>),
+}
+
+// Invariants:
+//  - `jieba` must contain exactly the entries of `base`, the enabled
+//    `layers` and `words`, merged in that order, that are not in
+//    `disabled`;
+//  - `disabled` must be a subset of the words in `base`, `layers` and
+//    `words`.
+#[derive(Clone)]
 struct Dict {
     name: DictName,
-    jieba: jieba::Jieba,
+    base: sync::Arc<Words>,
+    layers: Vec<Layer>,
+    words: Words,
+    disabled: collections::BTreeSet<String>,
+    hmm: Option<sync::Arc<Hmm>>,
+    // Shared with the documents and batches running in the background.
+    jieba: sync::Arc<jieba::Jieba>,
+    // Reset whenever `jieba` or `hmm` changes.
+    fingerprint: sync::OnceLock<u64>,
+}
+
+#[derive(Clone)]
+struct Layer {
+    name: String,
+    words: Words,
+    enabled: bool,
+}
+
+type Words = collections::BTreeMap<String, Entry>;
+
+#[derive(Default, Clone, PartialEq)]
+struct Entry {
+    freq: usize,
+    tag: String,
+}
+
+const FORMATS: [Format; 6] = [
+    Format::Jieba,
+    Format::Csv,
+    Format::Tsv,
+    Format::Plain,
+    Format::HanLp,
+    Format::Pkuseg,
+];
+#[derive(Default, Copy, Clone, PartialEq)]
+enum Format {
+    #[default]
+    Jieba,
+    Csv,
+    Tsv,
+    Plain,
+    HanLp,
+    Pkuseg,
 }
 
+// Fields of a line in some format, if any.
+struct Fields<'a> {
+    word: Option<borrow::Cow<'a, str>>,
+    freq: Option<borrow::Cow<'a, str>>,
+    tag: Option<borrow::Cow<'a, str>>,
+}
+
+#[derive(Clone, PartialEq)]
 enum DictName {
     Embedded(Embedded),
-    File(String),
+    Custom(String),
 }
 
-#[derive(Copy, Clone)]
+const EMBEDDEDS: [Embedded; 3] = [Embedded::Normal, Embedded::Small, Embedded::Big];
+#[derive(Copy, Clone, PartialEq)]
 enum Embedded {
     Normal,
     Small,
     Big,
 }
 
+struct Diagnostic {
+    line_no: usize,
+    problem: Problem,
+}
+
+enum Problem {
+    InvalidUtf8,
+    InvalidFreq(String),
+    UnknownTag(String),
+    Duplicated(String),
+}
+
+struct CheckDialog {
+    id: egui::Id,
+    name: String,
+    kind: CheckKind,
+    words: Words,
+    diagnostics: Vec<Diagnostic>,
+}
+
+#[derive(Copy, Clone)]
+enum CheckKind {
+    New,
+    Load,
+}
+
+enum CheckChoice {
+    Skip,
+    Export,
+    Cancel,
+}
+
 struct ErrorDialog {
     id: egui::Id,
     what: String,
     content: String,
 }
 
+// What is needed to run operations, shared by batch workers.
+#[derive(Copy, Clone)]
+struct Segmenter<'a> {
+    jieba: &'a jieba::Jieba,
+    hmm: Option<&'a Hmm>,
+    use_hmm: bool,
+}
+
+// What a `Segmenter` borrows, owned to be sent to background threads.
+#[derive(Clone)]
+struct Runner {
+    jieba: sync::Arc<jieba::Jieba>,
+    hmm: Option<sync::Arc<Hmm>>,
+    use_hmm: bool,
+}
+
+// FNV-1a hash of everything written to it.
+struct FnvHasher(u64);
+
 impl App {
     fn new(cc: &eframe::CreationContext) -> Self {
         cc.egui_ctx.set_fonts(make_cjk_font_defs());
@@ -104,7 +505,58 @@ impl App {
             opt.theme_preference = Theme::default().into();
             opt.fallback_theme = egui::Theme::Light;
         });
-        Self::default()
+        let mut app = Self {
+            preview_lines: DEFAULT_PREVIEW_LINES,
+            ..Default::default()
+        };
+        if let Some(preferences) = cc
+            .storage
+            .and_then(|storage| storage.get_string(PREFERENCES_KEY))
+        {
+            app.read_preferences(&preferences);
+        }
+        app
+    }
+}
+
+impl Default for Docs {
+    fn default() -> Self {
+        Docs {
+            idx: 0,
+            docs: vec![Doc::default()],
+        }
+    }
+}
+
+const COMMAND_SHIFT: egui::Modifiers = egui::Modifiers {
+    shift: true,
+    ..egui::Modifiers::COMMAND
+};
+const DEFAULT_SHORTCUTS: [(Command, egui::Modifiers, egui::Key); 10] = [
+    (Command::Palette, COMMAND_SHIFT, egui::Key::P),
+    (Command::OpenProject, COMMAND_SHIFT, egui::Key::O),
+    (Command::SaveProject, COMMAND_SHIFT, egui::Key::S),
+    (Command::NewDoc, egui::Modifiers::COMMAND, egui::Key::T),
+    (Command::CloseDoc, egui::Modifiers::COMMAND, egui::Key::W),
+    (Command::Import, egui::Modifiers::COMMAND, egui::Key::O),
+    (Command::PasteAndRun, COMMAND_SHIFT, egui::Key::V),
+    (Command::Export, egui::Modifiers::COMMAND, egui::Key::S),
+    (Command::Copy(CopyFormat::Text), COMMAND_SHIFT, egui::Key::C),
+    (
+        Command::Run(Operation::Segment),
+        egui::Modifiers::COMMAND,
+        egui::Key::Enter,
+    ),
+];
+impl Default for Shortcuts {
+    fn default() -> Self {
+        let mut shortcuts = Shortcuts {
+            bindings: Vec::new(),
+        };
+        for (command, modifiers, key) in DEFAULT_SHORTCUTS {
+            shortcuts.set(command, Some(egui::KeyboardShortcut::new(modifiers, key)));
+        }
+        shortcuts
     }
 }
 
@@ -112,17 +564,43 @@ impl Default for Dicts {
     fn default() -> Self {
         Dicts {
             idx: 0,
-            dicts: vec![
-                make_dict_static(Embedded::Normal, include_bytes!("../dicts/dict.txt")),
-                make_dict_static(Embedded::Small, include_bytes!("../dicts/dict.txt.small")),
-                make_dict_static(Embedded::Big, include_bytes!("../dicts/dict.txt.big")),
+            pending: None,
+            slots: vec![
+                Slot::Loaded(Embedded::Normal.load()),
+                Slot::Unloaded(Embedded::Small),
+                Slot::Unloaded(Embedded::Big),
             ],
+            ids: vec![0, 1, 2],
+            next_id: 3,
+            history: collections::VecDeque::new(),
+            future: Vec::new(),
         }
     }
 }
 
 impl eframe::App for App {
+    fn save(&mut self, storage: &mut dyn eframe::Storage) {
+        storage.set_string(PREFERENCES_KEY, self.write_preferences());
+    }
+
     fn ui(&mut self, ui: &mut egui::Ui, _frame: &mut eframe::Frame) {
+        self.poll_clipboard(ui);
+        self.docs.poll_running();
+        self.poll_batch_preview();
+        self.poll_batch();
+        self.poll_training_hmm();
+        self.handle_shortcuts(ui);
+        self.show_palette(ui);
+        if let Some(dialog) = &mut self.check_dialog
+            && let Some(choice) = dialog.show(ui).inner
+        {
+            match choice {
+                CheckChoice::Skip => self.close_check(),
+                CheckChoice::Export => self.export_check(),
+                CheckChoice::Cancel => self.check_dialog = None,
+            }
+            self.next_check();
+        }
         if let Some(dialog) = &mut self.error_dialog
             && dialog.show(ui).backdrop_response.clicked()
         {
@@ -131,21 +609,30 @@ impl eframe::App for App {
         egui::Panel::top("menu area").show(ui, |ui| {
             self.show_menu_area(ui);
         });
+        let mut dict_rect = egui::Rect::NOTHING;
+        let mut layers_rect = egui::Rect::NOTHING;
+        let mut input_point = None;
+        let mut output_point = None;
         egui::CentralPanel::default().show(ui, |ui| {
-            egui::Panel::left("dict panel")
+            let panel = egui::Panel::left("dict panel")
                 .resizable(false)
-                .show(ui, |ui| {
-                    self.show_dict_panel(ui);
-                });
+                .show(ui, |ui| self.show_dict_panel(ui));
+            dict_rect = panel.response.rect;
+            layers_rect = panel.inner;
+            egui::Panel::top("doc tabs").show(ui, |ui| {
+                self.show_doc_tabs(ui);
+            });
             egui::Panel::top("input area")
                 .exact_size(ui.available_height() / 2.0)
                 .show(ui, |ui| {
-                    self.show_input_area(ui);
+                    input_point = self.show_input_area(ui);
                 });
             egui::CentralPanel::default().show(ui, |ui| {
-                self.show_output_area(ui);
+                output_point = self.show_output_area(ui);
             });
         });
+        self.focus_item(input_point, output_point);
+        self.drop_files(ui, dict_rect, layers_rect);
     }
 }
 
@@ -157,15 +644,35 @@ const GHREPO_LINK: &str = "https://github.com/usaoc/chissor";
 impl App {
     fn show_menu_area(&mut self, ui: &mut egui::Ui) {
         ui.horizontal(|ui| {
+            if ui
+                .button(t!("command-palette.text"))
+                .on_hover_text(t!("command-palette.hover"))
+                .clicked()
+            {
+                self.open_palette();
+            }
+            ui.menu_button(t!("menu.project.text"), |ui| {
+                if ui
+                    .button(t!("open-project.text"))
+                    .on_hover_text(t!("open-project.hover"))
+                    .clicked()
+                {
+                    self.open_project();
+                }
+                if ui
+                    .button(t!("save-project.text"))
+                    .on_hover_text(t!("save-project.hover"))
+                    .clicked()
+                {
+                    self.save_project();
+                }
+            })
+            .response
+            .on_hover_text(t!("menu.project.hover"));
             ui.menu_button(t!("menu.output.text"), |ui| {
-                ui.add(
-                    egui::TextEdit::singleline(&mut self.separator).hint_text(t!("separator.text")),
-                )
-                .on_hover_text(t!("separator.hover"));
-                ui.checkbox(&mut self.use_hmm, t!("use-hmm.text"))
-                    .on_hover_text(t!("use-hmm.hover"));
-                ui.checkbox(&mut self.batch_mode, t!("batch-mode.text"))
-                    .on_hover_text(t!("batch-mode.hover"));
+                self.show_settings(ui);
+                ui.separator();
+                self.show_batch_options(ui);
             })
             .response
             .on_hover_text(t!("menu.output.hover"));
@@ -213,7 +720,41 @@ impl App {
         });
     }
 
-    fn show_dict_panel(&mut self, ui: &mut egui::Ui) {
+    // Return the area of the layers, where dropped files are loaded.
+    fn show_dict_panel(&mut self, ui: &mut egui::Ui) -> egui::Rect {
+        self.show_dict_actions(ui);
+        self.show_word_actions(ui);
+        let layers = egui::CollapsingHeader::new(t!("layers.text")).show(ui, |ui| {
+            self.dicts.show_layers(ui);
+        });
+        let mut layers_rect = layers
+            .header_response
+            .on_hover_text(t!("layers.hover"))
+            .rect;
+        if let Some(body) = layers.body_response {
+            layers_rect = layers_rect.union(body.rect);
+        }
+        egui::CollapsingHeader::new(t!("disabled-words.text"))
+            .show(ui, |ui| {
+                self.dicts.show_disabled(ui);
+            })
+            .header_response
+            .on_hover_text(t!("disabled-words.hover"));
+        egui::CollapsingHeader::new(t!("history.text"))
+            .show(ui, |ui| {
+                self.dicts.show_history(ui);
+            })
+            .header_response
+            .on_hover_text(t!("history.hover"));
+        ui.separator();
+        egui::ScrollArea::vertical().show(ui, |ui| {
+            self.dicts.show_all(ui);
+        });
+        self.docs.selected_mut().dict = self.dicts.selected_id();
+        layers_rect
+    }
+
+    fn show_dict_actions(&mut self, ui: &mut egui::Ui) {
         ui.horizontal(|ui| {
             if ui
                 .button(t!("new-dict.text"))
@@ -230,11 +771,11 @@ impl App {
                 self.load_dict();
             }
             if ui
-                .add_enabled(self.can_add_word(), egui::Button::new(t!("add-word.text")))
-                .on_hover_text(t!("add-word.hover"))
+                .button(t!("export-dict.text"))
+                .on_hover_text(t!("export-dict.hover"))
                 .clicked()
             {
-                self.add_word();
+                self.export_dict();
             }
             if ui
                 .add_enabled(
@@ -247,6 +788,62 @@ impl App {
                 self.dicts.remove_dict();
             }
         });
+        ui.horizontal(|ui| {
+            ui.add(egui::TextEdit::singleline(&mut self.dict_name).hint_text(t!("dict-name.text")))
+                .on_hover_text(t!("dict-name.hover"));
+            if ui
+                .add_enabled(
+                    self.can_rename_dict(),
+                    egui::Button::new(t!("rename-dict.text")),
+                )
+                .on_hover_text(t!("rename-dict.hover"))
+                .clicked()
+            {
+                self.rename_dict();
+            }
+        });
+        ui.horizontal(|ui| {
+            if ui
+                .button(t!("duplicate-dict.text"))
+                .on_hover_text(t!("duplicate-dict.hover"))
+                .clicked()
+            {
+                self.dicts.duplicate_dict();
+            }
+            if ui
+                .add_enabled(
+                    self.dicts.can_move_up(),
+                    egui::Button::new(t!("move-up-dict.text")),
+                )
+                .on_hover_text(t!("move-up-dict.hover"))
+                .clicked()
+            {
+                self.dicts.move_up();
+            }
+            if ui
+                .add_enabled(
+                    self.dicts.can_move_down(),
+                    egui::Button::new(t!("move-down-dict.text")),
+                )
+                .on_hover_text(t!("move-down-dict.hover"))
+                .clicked()
+            {
+                self.dicts.move_down();
+            }
+        });
+        ui.horizontal(|ui| self.show_hmm_actions(ui));
+        egui::ComboBox::from_id_salt("dict format")
+            .selected_text(self.format.to_name())
+            .show_ui(ui, |ui| {
+                for format in FORMATS {
+                    ui.selectable_value(&mut self.format, format, format.to_name());
+                }
+            })
+            .response
+            .on_hover_text(t!("format.hover"));
+    }
+
+    fn show_word_actions(&mut self, ui: &mut egui::Ui) {
         ui.add(
             egui::TextEdit::singleline(&mut self.word)
                 .hint_text(t!("word.text"))
@@ -269,13 +866,67 @@ impl App {
             )
             .on_hover_text(t!("word.tag.hover"));
         });
-        ui.separator();
-        egui::ScrollArea::vertical().show(ui, |ui| {
-            self.dicts.show_all(ui);
+        ui.horizontal(|ui| {
+            if ui
+                .add_enabled(self.can_add_word(), egui::Button::new(t!("add-word.text")))
+                .on_hover_text(t!("add-word.hover"))
+                .clicked()
+            {
+                self.add_word();
+            }
+            if ui
+                .add_enabled(
+                    self.can_delete_word(),
+                    egui::Button::new(t!("delete-word.text")),
+                )
+                .on_hover_text(t!("delete-word.hover"))
+                .clicked()
+            {
+                self.delete_word();
+            }
+            if ui
+                .add_enabled(
+                    self.can_disable_word(),
+                    egui::Button::new(t!("disable-word.text")),
+                )
+                .on_hover_text(t!("disable-word.hover"))
+                .clicked()
+            {
+                self.disable_word();
+            }
+        });
+    }
+
+    fn show_doc_tabs(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            for idx in 0..self.docs.docs.len() {
+                let name = self.docs.docs[idx].to_name(idx);
+                if ui.selectable_label(self.docs.idx == idx, name).clicked() {
+                    self.select_doc(idx);
+                }
+            }
+            if ui
+                .button(t!("new-doc.text"))
+                .on_hover_text(t!("new-doc.hover"))
+                .clicked()
+            {
+                self.run_command(Command::NewDoc);
+            }
+            if ui
+                .add_enabled(
+                    self.docs.can_close_doc(),
+                    egui::Button::new(t!("close-doc.text")),
+                )
+                .on_hover_text(t!("close-doc.hover"))
+                .clicked()
+            {
+                self.run_command(Command::CloseDoc);
+            }
         });
     }
 
-    fn show_input_area(&mut self, ui: &mut egui::Ui) {
+    // Return the character index pointed at in the input text, if any.
+    fn show_input_area(&mut self, ui: &mut egui::Ui) -> Option<usize> {
         ui.add_enabled_ui(!self.batch_mode, |ui| {
             ui.horizontal(|ui| {
                 if ui
@@ -285,18 +936,53 @@ impl App {
                 {
                     self.import();
                 }
+                if ui
+                    .button(t!("paste-and-run.text"))
+                    .on_hover_text(t!("paste-and-run.hover"))
+                    .clicked()
+                {
+                    self.paste_and_run();
+                }
+                ui.checkbox(&mut self.clipboard.watch, t!("watch-clipboard.text"))
+                    .on_hover_text(t!("watch-clipboard.hover"));
             });
+            if self.docs.selected().input.len() > LARGE_TEXT_LEN {
+                ui.horizontal(|ui| {
+                    ui.label(t!("large-input.text"));
+                    if ui
+                        .button(t!("large-input.batch-mode"))
+                        .on_hover_text(t!("large-input.hover"))
+                        .clicked()
+                    {
+                        self.batch_mode = true;
+                    }
+                });
+            }
             ui.separator();
-            egui::ScrollArea::vertical().show(ui, |ui| {
-                ui.add_sized(
-                    ui.available_size(),
-                    egui::TextEdit::multiline(&mut self.input).hint_text(t!("input.text")),
-                );
-            });
-        });
+            egui::ScrollArea::vertical()
+                .show(ui, |ui| {
+                    let highlight = self.highlight(|item| item.input.clone());
+                    let doc = self.docs.selected_mut();
+                    let mut layouter = |ui: &egui::Ui, text: &dyn egui::TextBuffer, width| {
+                        layout_highlighted(ui, text.as_str(), highlight.clone(), width)
+                    };
+                    let output = egui::TextEdit::multiline(&mut doc.input)
+                        .hint_text(t!("input.text"))
+                        .min_size(ui.available_size())
+                        .layouter(&mut layouter)
+                        .show(ui);
+                    if output.response.changed() {
+                        doc.edited = true;
+                    }
+                    get_point(&output)
+                })
+                .inner
+        })
+        .inner
     }
 
-    fn show_output_area(&mut self, ui: &mut egui::Ui) {
+    // Return the character index pointed at in the output result, if any.
+    fn show_output_area(&mut self, ui: &mut egui::Ui) -> Option<usize> {
         ui.horizontal(|ui| {
             if ui
                 .add_enabled(!self.batch_mode, egui::Button::new(t!("export.text")))
@@ -305,71 +991,226 @@ impl App {
             {
                 self.export();
             }
+            ui.add_enabled_ui(!self.batch_mode, |ui| {
+                ui.menu_button(t!("copy.text"), |ui| {
+                    for format in COPY_FORMATS {
+                        let button = egui::Button::new(format.to_name());
+                        if ui
+                            .add_enabled(self.can_copy_output(format), button)
+                            .clicked()
+                        {
+                            self.copy_output(format);
+                        }
+                    }
+                })
+                .response
+                .on_hover_text(t!("copy.hover"));
+            });
+            let enabled = self.can_run_command(Command::RerunBatch);
             if ui
-                .button(t!("segment.text"))
-                .on_hover_text(t!("segment.hover"))
-                .clicked()
-            {
-                if self.batch_mode {
-                    self.segment_batch();
-                } else {
-                    self.segment();
-                }
-            }
-            if ui
-                .button(t!("segment-granular.text"))
-                .on_hover_text(t!("segment-granular.hover"))
-                .clicked()
-            {
-                if self.batch_mode {
-                    self.segment_granular_batch();
-                } else {
-                    self.segment_granular();
-                }
-            }
-            if ui
-                .button(t!("search.text"))
-                .on_hover_text(t!("search.hover"))
+                .add_enabled(enabled, egui::Button::new(t!("rerun-batch.text")))
+                .on_hover_text(t!("rerun-batch.hover"))
                 .clicked()
             {
-                if self.batch_mode {
-                    self.search_batch();
-                } else {
-                    self.search();
-                }
+                self.rerun_batch();
             }
-            if ui
-                .button(t!("tag.text"))
-                .on_hover_text(t!("tag.hover"))
-                .clicked()
-            {
-                if self.batch_mode {
-                    self.tag_batch();
-                } else {
-                    self.tag();
+            for operation in OPERATIONS {
+                let enabled = self.can_run_command(Command::Run(operation));
+                if ui
+                    .add_enabled(enabled, egui::Button::new(operation.to_text()))
+                    .on_hover_text(operation.to_hover())
+                    .clicked()
+                {
+                    self.run_command(Command::Run(operation));
                 }
             }
         });
+        if self.batch_mode {
+            self.show_batch_status(ui);
+        }
+        if self.batch_mode && self.batch_preview.is_some() {
+            self.show_batch_preview(ui);
+            return None;
+        }
         ui.add_enabled_ui(!self.batch_mode, |ui| {
             ui.separator();
-            egui::ScrollArea::vertical().show(ui, |ui| {
-                ui.add_sized(
-                    ui.available_size(),
-                    egui::TextEdit::multiline(&mut &*self.output).hint_text(t!("output.text")),
-                );
-            });
-        });
+            let doc = self.docs.selected_mut();
+            if doc.running.is_some() {
+                // The spinner also keeps polling the result.
+                ui.spinner().on_hover_text(t!("running.hover"));
+                return None;
+            }
+            if doc.output.len() > LARGE_TEXT_LEN {
+                // Rows are computed on demand, since only large output
+                // results are shown by rows.
+                if doc.rows.is_empty() {
+                    doc.rows = split_rows(&doc.output, ROW_LEN);
+                }
+                show_rows(ui, &doc.output, &doc.rows);
+                return None;
+            }
+            egui::ScrollArea::vertical()
+                .show(ui, |ui| {
+                    let highlight = self.highlight(|item| item.output.clone());
+                    let mut layouter = |ui: &egui::Ui, text: &dyn egui::TextBuffer, width| {
+                        layout_highlighted(ui, text.as_str(), highlight.clone(), width)
+                    };
+                    let output = egui::TextEdit::multiline(&mut &*self.docs.selected().output)
+                        .hint_text(t!("output.text"))
+                        .min_size(ui.available_size())
+                        .layouter(&mut layouter)
+                        .show(ui);
+                    get_point(&output)
+                })
+                .inner
+        })
+        .inner
     }
 
-    fn new_dict(&mut self) {
-        if let Err(err) = with_pick_file(|path| {
-            let name = String::from(
-                path.file_name()
-                    .expect("cannot be `None`; must have picked a regular file")
-                    .to_string_lossy(),
-            );
-            let file = fs::File::open(path)?;
-            self.dicts.new_dict(name, &mut io::BufReader::new(file))?;
+    // The ranges of the result items become stale once the input text
+    // is edited, and laying out large text again is too slow.
+    fn highlight(
+        &self,
+        func: impl FnOnce(&Item) -> ops::Range<usize>,
+    ) -> Option<ops::Range<usize>> {
+        let doc = self.docs.selected();
+        if doc.edited || doc.input.len() > LARGE_TEXT_LEN {
+            return None;
+        }
+        self.focus.and_then(|idx| doc.items.get(idx)).map(func)
+    }
+
+    // Prefer the output result, where the items do not overlap.
+    fn focus_item(&mut self, input_point: Option<usize>, output_point: Option<usize>) {
+        let items = &self.docs.selected().items;
+        let find = |point, func: fn(&Item) -> &ops::Range<usize>| {
+            items.iter().position(|item| func(item).contains(&point))
+        };
+        self.focus = output_point
+            .and_then(|point| find(point, |item| &item.output))
+            .or_else(|| input_point.and_then(|point| find(point, |item| &item.input)));
+    }
+
+    fn show_palette(&mut self, ui: &mut egui::Ui) {
+        if self.palette.is_none() {
+            return;
+        }
+        let enabled: Vec<_> = COMMANDS
+            .into_iter()
+            .filter(|&command| self.can_run_command(command))
+            .collect();
+        let palette = self
+            .palette
+            .as_mut()
+            .expect("cannot be `None`; checked above");
+        let response = palette.show(ui, &mut self.shortcuts, &enabled);
+        if let Some(command) = response.inner {
+            self.palette = None;
+            self.run_command(command);
+        } else if palette.recording.is_none() && response.should_close() {
+            self.palette = None;
+        }
+    }
+
+    fn open_palette(&mut self) {
+        self.palette = Some(CommandPalette {
+            id: egui::Id::new("command_palette"),
+            query: String::new(),
+            focus: true,
+            recording: None,
+        });
+    }
+
+    // Shortcuts are ignored while any dialog is open.
+    fn handle_shortcuts(&mut self, ui: &egui::Ui) {
+        if self.palette.is_some() || self.check_dialog.is_some() || self.error_dialog.is_some() {
+            return;
+        }
+        if let Some(command) = ui.input_mut(|input| self.shortcuts.consume(input))
+            && self.can_run_command(command)
+        {
+            self.run_command(command);
+        }
+    }
+
+    fn can_run_command(&self, command: Command) -> bool {
+        match command {
+            Command::RemoveDict => self.dicts.can_remove_dict(),
+            Command::RenameDict => self.can_rename_dict(),
+            Command::MoveUpDict => self.dicts.can_move_up(),
+            Command::MoveDownDict => self.dicts.can_move_down(),
+            Command::ExportHmm => self.can_export_hmm(),
+            Command::ResetHmm => self.dicts.can_reset_hmm(),
+            Command::AddWord => self.can_add_word(),
+            Command::DeleteWord => self.can_delete_word(),
+            Command::DisableWord => self.can_disable_word(),
+            Command::Undo => self.dicts.can_undo(),
+            Command::Redo => self.dicts.can_redo(),
+            Command::CloseDoc => self.docs.can_close_doc(),
+            Command::Import | Command::PasteAndRun | Command::WatchClipboard | Command::Export => {
+                !self.batch_mode
+            }
+            Command::Copy(format) => self.can_copy_output(format),
+            Command::RerunBatch => self.batch_mode && self.running_batch.is_none(),
+            // One batch runs at a time.
+            Command::Run(_) => !(self.batch_mode && self.running_batch.is_some()),
+            Command::TrainHmm => self.training_hmm.is_none(),
+            _ => true,
+        }
+    }
+
+    fn run_command(&mut self, command: Command) {
+        assert!(
+            self.can_run_command(command),
+            "must not trigger this action for disabled command",
+        );
+        match command {
+            Command::Palette => self.open_palette(),
+            Command::OpenProject => self.open_project(),
+            Command::SaveProject => self.save_project(),
+            // The operation run next, rather than the one shown in the
+            // output settings.
+            Command::UseHmm => {
+                let settings = self.settings_mut(self.docs.selected().operation);
+                settings.use_hmm = !settings.use_hmm;
+            }
+            Command::BatchMode => self.batch_mode = !self.batch_mode,
+            Command::NewDict => self.new_dict(),
+            Command::LoadDict => self.load_dict(),
+            Command::ExportDict => self.export_dict(),
+            Command::RemoveDict => self.dicts.remove_dict(),
+            Command::RenameDict => self.rename_dict(),
+            Command::DuplicateDict => self.dicts.duplicate_dict(),
+            Command::MoveUpDict => self.dicts.move_up(),
+            Command::MoveDownDict => self.dicts.move_down(),
+            Command::LoadHmm => self.load_hmm(),
+            Command::TrainHmm => self.train_hmm(),
+            Command::ExportHmm => self.export_hmm(),
+            Command::ResetHmm => self.dicts.reset_hmm(),
+            Command::AddWord => self.add_word(),
+            Command::DeleteWord => self.delete_word(),
+            Command::DisableWord => self.disable_word(),
+            Command::Undo => self.dicts.undo(),
+            Command::Redo => self.dicts.redo(),
+            Command::NewDoc => self.docs.new_doc(self.dicts.selected_id()),
+            Command::CloseDoc => {
+                self.docs.close_doc();
+                self.select_doc(self.docs.idx);
+            }
+            Command::Import => self.import(),
+            Command::PasteAndRun => self.paste_and_run(),
+            Command::WatchClipboard => self.clipboard.watch = !self.clipboard.watch,
+            Command::Export => self.export(),
+            Command::RerunBatch => self.rerun_batch(),
+            Command::Copy(format) => self.copy_output(format),
+            Command::Run(operation) if self.batch_mode => self.run_batch(operation),
+            Command::Run(operation) => self.run(operation),
+        }
+    }
+
+    fn new_dict(&mut self) {
+        if let Err(err) = with_pick_file(|path| {
+            self.open_check(path, CheckKind::New)?;
             Ok(())
         }) {
             self.open_error("new-dict.what", err);
@@ -378,14 +1219,113 @@ impl App {
 
     fn load_dict(&mut self) {
         if let Err(err) = with_pick_file(|path| {
-            let file = fs::File::open(path)?;
-            self.dicts.load_dict(&mut io::BufReader::new(file))?;
+            self.open_check(path, CheckKind::Load)?;
             Ok(())
         }) {
             self.open_error("load-dict.what", err);
         }
     }
 
+    // Only bother the user with a dialog when the file has problems.
+    fn open_check(&mut self, path: path::PathBuf, kind: CheckKind) -> Result<()> {
+        assert!(
+            self.check_dialog.is_none(),
+            "must not have multiple check dialogs",
+        );
+        let defaults = Entry {
+            freq: if self.freq.is_empty() {
+                0
+            } else {
+                self.freq.parse()?
+            },
+            tag: self.tag.clone(),
+        };
+        let name = get_file_name(&path);
+        let file = fs::File::open(path)?;
+        let (words, diagnostics) =
+            check_dict(&mut io::BufReader::new(file), self.format, &defaults)?;
+        let dialog = CheckDialog {
+            id: egui::Id::new("check_dialog"),
+            name,
+            kind,
+            words,
+            diagnostics,
+        };
+        if dialog.diagnostics.is_empty() {
+            self.apply_check(dialog);
+        } else {
+            self.check_dialog = Some(dialog);
+        }
+        Ok(())
+    }
+
+    fn queue_checks(&mut self, paths: Vec<path::PathBuf>, kind: CheckKind) {
+        self.checks
+            .extend(paths.into_iter().map(|path| (path, kind)));
+        self.next_check();
+    }
+
+    // Files with problems are checked one dialog at a time.
+    fn next_check(&mut self) {
+        while self.check_dialog.is_none()
+            && let Some((path, kind)) = self.checks.pop_front()
+        {
+            if let Err(err) = self.open_check(path, kind) {
+                self.checks.clear();
+                self.open_error(kind.to_what(), err);
+            }
+        }
+    }
+
+    fn close_check(&mut self) {
+        let dialog = self.check_dialog.take().expect("must have check dialog");
+        self.apply_check(dialog);
+    }
+
+    fn apply_check(&mut self, dialog: CheckDialog) {
+        let CheckDialog {
+            name, kind, words, ..
+        } = dialog;
+        match kind {
+            CheckKind::New => self.dicts.new_dict(name, words),
+            CheckKind::Load => self.dicts.load_dict(name, words),
+        }
+    }
+
+    fn export_check(&mut self) {
+        let dialog = self.check_dialog.as_ref().expect("must have check dialog");
+        if let Err(err) = with_save_file(|path| {
+            let mut buf = io::BufWriter::new(fs::File::create(path)?);
+            dialog.write_report(&mut buf)?;
+            Ok(())
+        }) {
+            self.open_error("check-dialog.export.what", err);
+        }
+    }
+
+    fn export_dict(&mut self) {
+        if let Err(err) = with_save_file(|path| {
+            let file = fs::File::create(path)?;
+            self.dicts
+                .export_dict(&mut io::BufWriter::new(file), self.format)?;
+            Ok(())
+        }) {
+            self.open_error("export-dict.what", err);
+        }
+    }
+
+    fn can_rename_dict(&self) -> bool {
+        !self.dict_name.trim().is_empty()
+    }
+
+    fn rename_dict(&mut self) {
+        assert!(
+            self.can_rename_dict(),
+            "must not trigger this action for empty name",
+        );
+        self.dicts.rename_dict(self.dict_name.trim());
+    }
+
     fn can_add_word(&self) -> bool {
         !self.word.is_empty()
     }
@@ -400,108 +1340,188 @@ impl App {
         }
     }
 
-    fn import(&mut self) {
-        if let Err(err) = with_pick_file(|path| {
-            self.input = String::from(fs::read_to_string(path)?.trim());
-            Ok(())
-        }) {
-            self.open_error("import.what", err);
-        }
+    fn can_delete_word(&self) -> bool {
+        self.dicts.has_word(&self.word)
     }
 
-    fn export(&mut self) {
-        if let Err(err) = with_save_file(|path| {
-            let mut buf = fs::File::create(path)?;
-            writeln!(&mut buf, "{output}", output = self.output)?;
-            Ok(())
-        }) {
-            self.open_error("export.what", err);
-        }
+    fn delete_word(&mut self) {
+        assert!(
+            self.can_delete_word(),
+            "must not trigger this action for absent word",
+        );
+        self.dicts.delete_word(&self.word);
+    }
+
+    fn can_disable_word(&self) -> bool {
+        self.dicts.has_word(&self.word) && !self.dicts.is_disabled(&self.word)
     }
 
-    fn segment(&mut self) {
-        self.output = self.segment_one(&self.input);
+    fn disable_word(&mut self) {
+        assert!(
+            self.can_disable_word(),
+            "must not trigger this action for absent or disabled word",
+        );
+        self.dicts.disable_word(&self.word);
     }
 
-    fn segment_granular(&mut self) {
-        self.output = self.segment_granular_one(&self.input);
+    fn select_doc(&mut self, idx: usize) {
+        self.docs.idx = idx;
+        self.dicts.select_id(self.docs.selected().dict);
     }
 
-    fn search(&mut self) {
-        self.output = self.search_one(&self.input);
+    fn import(&mut self) {
+        if let Err(err) = with_pick_file(|path| self.import_file(&path)) {
+            self.open_error("import.what", err);
+        }
     }
 
-    fn tag(&mut self) {
-        self.output = self.tag_one(&self.input);
+    fn import_file(&mut self, path: &path::Path) -> Result<()> {
+        let doc = self.docs.selected_mut();
+        doc.input = String::from(fs::read_to_string(path)?.trim());
+        doc.edited = true;
+        doc.name = Some(get_file_name(path));
+        Ok(())
     }
 
-    fn segment_batch(&mut self) {
-        if let Err(err) = with_out_files(|input| self.segment_one(input)) {
-            self.open_error("segment.what", err);
+    // Files dropped onto the layers are loaded, onto the rest of the
+    // dictionary panel are initialized as dictionaries, and elsewhere
+    // are imported, or run as a batch job with the operation last run
+    // if there are several or in batch mode.  Some platforms report no
+    // pointer position with the drop, in which case the last one seen
+    // while the files were hovered is used.
+    fn drop_files(&mut self, ui: &egui::Ui, dict_rect: egui::Rect, layers_rect: egui::Rect) {
+        let (paths, hovered, pos) = ui.input(|input| {
+            let paths: Vec<_> = input
+                .raw
+                .dropped_files
+                .iter()
+                .filter_map(|file| file.path.clone())
+                .collect();
+            let hovered = !input.raw.hovered_files.is_empty();
+            (paths, hovered, input.pointer.latest_pos())
+        });
+        if hovered {
+            self.drop_pos = pos.or(self.drop_pos);
+        }
+        if paths.is_empty() {
+            if !hovered {
+                self.drop_pos = None;
+            }
+            return;
+        }
+        let pos = pos.or(self.drop_pos.take());
+        if self.check_dialog.is_some() || self.error_dialog.is_some() {
+            return;
+        }
+        match pos {
+            Some(pos) if layers_rect.contains(pos) => self.queue_checks(paths, CheckKind::Load),
+            Some(pos) if dict_rect.contains(pos) => self.queue_checks(paths, CheckKind::New),
+            _ if self.batch_mode || paths.len() > 1 => {
+                if self.running_batch.is_none() {
+                    self.start_batch(self.docs.selected().operation, paths);
+                }
+            }
+            _ => {
+                if let Err(err) = self.import_file(&paths[0]) {
+                    self.open_error("import.what", err);
+                }
+            }
         }
     }
 
-    fn segment_granular_batch(&mut self) {
-        if let Err(err) = with_out_files(|input| self.segment_granular_one(input)) {
-            self.open_error("segment-granular.what", err);
+    fn export(&mut self) {
+        if let Err(err) = with_save_file(|path| {
+            let mut buf = fs::File::create(path)?;
+            writeln!(&mut buf, "{output}", output = self.docs.selected().output)?;
+            Ok(())
+        }) {
+            self.open_error("export.what", err);
         }
     }
 
-    fn search_batch(&mut self) {
-        if let Err(err) = with_out_files(|input| self.search_one(input)) {
-            self.open_error("search.what", err);
+    // Large input is run in the background, so that the UI stays
+    // responsive, and the result replaces the output once it is ready.
+    fn run(&mut self, operation: Operation) {
+        let input = &self.docs.selected().input;
+        let running = if input.len() <= LARGE_TEXT_LEN {
+            let tokens = self.segmenter(operation).run_tokens(operation, input);
+            let (output, items) = self.get_layout(operation).join(&tokens);
+            let doc = self.docs.selected_mut();
+            doc.output = output;
+            doc.items = items;
+            None
+        } else {
+            let (runner, input) = (self.runner(operation), input.clone());
+            let settings = self.settings(operation).clone();
+            let (sender, receiver) = mpsc::channel();
+            thread::spawn(move || {
+                let tokens = runner.segmenter().run_tokens(operation, &input);
+                // The receiver is gone if the document is closed or run
+                // again meanwhile.
+                let _ = sender.send(settings.to_layout(operation).join(&tokens));
+            });
+            Some(receiver)
+        };
+        let doc = self.docs.selected_mut();
+        doc.running = running;
+        doc.edited = false;
+        doc.rows.clear();
+        doc.operation = operation;
+    }
+
+    fn segmenter(&self, operation: Operation) -> Segmenter<'_> {
+        Segmenter {
+            jieba: self.dicts.selected(),
+            hmm: self.dicts.selected_dict().hmm.as_deref(),
+            use_hmm: self.settings(operation).use_hmm,
         }
     }
 
-    fn tag_batch(&mut self) {
-        if let Err(err) = with_out_files(|input| self.tag_one(input)) {
-            self.open_error("tag.what", err);
+    fn runner(&self, operation: Operation) -> Runner {
+        let dict = self.dicts.selected_dict();
+        Runner {
+            jieba: sync::Arc::clone(&dict.jieba),
+            hmm: dict.hmm.clone(),
+            use_hmm: self.settings(operation).use_hmm,
         }
     }
 
-    fn segment_one(&self, input: &str) -> String {
-        self.dicts
-            .selected()
-            .cut(input, self.use_hmm)
-            .into_iter()
-            .map(|jieba::Token { word, .. }| word)
-            .collect::<Vec<_>>()
-            .join(self.get_separator())
+    fn paste_and_run(&mut self) {
+        match self.clipboard.get_text() {
+            Ok(text) => self.run_text(text),
+            Err(err) => self.open_error("paste-and-run.what", err),
+        }
     }
 
-    fn segment_granular_one(&self, input: &str) -> String {
-        self.dicts
-            .selected()
-            .cut_for_search(input, self.use_hmm)
-            .into_iter()
-            .map(|jieba::Token { word, .. }| word)
-            .collect::<Vec<_>>()
-            .join(self.get_separator())
+    // Copied text goes into a new document, so that no input is lost.
+    fn poll_clipboard(&mut self, ui: &egui::Ui) {
+        if let Some(text) = self.clipboard.poll(ui.ctx()) {
+            let operation = self.docs.selected().operation;
+            self.docs.new_doc(self.dicts.selected_id());
+            let doc = self.docs.selected_mut();
+            doc.input = text;
+            doc.operation = operation;
+            self.run(operation);
+        }
     }
 
-    fn search_one(&self, input: &str) -> String {
-        self.dicts
-            .selected()
-            .cut_all(input)
-            .into_iter()
-            .map(|jieba::Token { word, .. }| word)
-            .collect::<Vec<_>>()
-            .join(self.get_separator())
+    // Run the operation last run on the selected document.
+    fn run_text(&mut self, text: String) {
+        let doc = self.docs.selected_mut();
+        doc.input = text;
+        let operation = doc.operation;
+        self.run(operation);
     }
 
-    fn tag_one(&self, input: &str) -> String {
-        self.dicts
-            .selected()
-            .tag(input, self.use_hmm)
-            .into_iter()
-            .map(|jieba::Tag { word, tag, .. }| format!("{word} {tag}"))
-            .collect::<Vec<_>>()
-            .join(self.get_separator())
+    fn can_copy_output(&self, format: CopyFormat) -> bool {
+        !self.batch_mode && format.can_render(self.docs.selected())
     }
 
-    fn get_separator(&self) -> &str {
-        let sep = &self.separator;
-        if sep.is_empty() { "\n" } else { sep }
+    fn copy_output(&mut self, format: CopyFormat) {
+        let text = format.render(self.docs.selected());
+        if let Err(err) = self.clipboard.set_text(text) {
+            self.open_error("copy.what", err);
+        }
     }
 
     #[allow(clippy::needless_pass_by_value)]
@@ -510,246 +1530,2372 @@ impl App {
             self.error_dialog.is_none(),
             "must not have multiple error dialogs",
         );
-        self.error_dialog = Some(ErrorDialog {
-            id: egui::Id::new("error_dialog"),
-            what: String::from(what),
-            content: err.to_string(),
-        });
+        self.error_dialog = Some(ErrorDialog::new(what, &*err));
     }
 
-    fn close_error(&mut self) {
-        assert!(self.error_dialog.is_some(), "must have error dialog");
-        self.error_dialog = None;
+    // Background work can finish at any time, so its errors wait for the
+    // open dialog to be closed.
+    #[allow(clippy::needless_pass_by_value)]
+    fn queue_error(&mut self, what: &str, err: Box<dyn error::Error>) {
+        self.errors.push_back(ErrorDialog::new(what, &*err));
+        self.next_error();
     }
-}
 
-impl From<Theme> for egui::ThemePreference {
-    fn from(val: Theme) -> Self {
-        match val {
-            Theme::System => Self::System,
-            Theme::Light => Self::Light,
-            Theme::Dark => Self::Dark,
+    fn next_error(&mut self) {
+        if self.error_dialog.is_none() {
+            self.error_dialog = self.errors.pop_front();
         }
     }
-}
 
-impl Theme {
-    fn to_name(self) -> impl Into<egui::WidgetText> {
-        match self {
-            Self::System => t!("theme.system.name"),
-            Self::Light => t!("theme.light.name"),
-            Self::Dark => t!("theme.dark.name"),
-        }
+    fn close_error(&mut self) {
+        assert!(self.error_dialog.is_some(), "must have error dialog");
+        self.error_dialog = None;
+        self.next_error();
     }
 }
 
-impl Locale {
-    fn to_locale(self) -> &'static str {
-        match self {
-            Self::En => "en",
-            Self::ZhCn => "zh-CN",
-            Self::ZhHk => "zh-HK",
-        }
-    }
-
-    fn to_name(self) -> &'static str {
+impl CheckKind {
+    fn to_what(self) -> &'static str {
         match self {
-            Self::En => "English",
-            Self::ZhCn => "中文（简体）",
-            Self::ZhHk => "中文（繁體）",
+            Self::New => "new-dict.what",
+            Self::Load => "load-dict.what",
         }
     }
 }
 
-impl Dicts {
-    fn new_dict(&mut self, name: impl Into<String>, dict: &mut impl io::BufRead) -> Result<()> {
-        let jieba = jieba::Jieba::with_dict(dict)?;
-        self.dicts.push(Dict {
-            name: DictName::File(name.into()),
-            jieba,
+impl Docs {
+    fn new_doc(&mut self, dict: usize) {
+        self.docs.push(Doc {
+            dict,
+            ..Default::default()
         });
-        Ok(())
+        self.idx = self.docs.len() - 1;
     }
 
-    fn load_dict(&mut self, dict: &mut impl io::BufRead) -> Result<()> {
-        self.selected_mut().load_dict(dict)?;
-        Ok(())
-    }
-
-    fn add_word(&mut self, word: &str, freq: &str, tag: &str) -> Result<()> {
-        let freq = if freq.is_empty() {
-            None
-        } else {
-            Some(freq.parse()?)
-        };
-        let tag = if tag.is_empty() { None } else { Some(tag) };
-        self.selected_mut().add_word(word, freq, tag);
-        Ok(())
+    fn can_close_doc(&self) -> bool {
+        self.docs.len() != 1
     }
 
-    fn can_remove_dict(&self) -> bool {
-        self.dicts.len() != 1
+    fn poll_running(&mut self) {
+        for doc in &mut self.docs {
+            if let Some(receiver) = &doc.running {
+                match receiver.try_recv() {
+                    Ok((output, items)) => {
+                        doc.output = output;
+                        doc.items = items;
+                        doc.running = None;
+                    }
+                    Err(mpsc::TryRecvError::Empty) => {}
+                    Err(mpsc::TryRecvError::Disconnected) => doc.running = None,
+                }
+            }
+        }
     }
 
-    fn remove_dict(&mut self) {
+    fn close_doc(&mut self) {
         assert!(
-            self.can_remove_dict(),
-            "must not trigger this action for the only dictionary",
+            self.can_close_doc(),
+            "must not trigger this action for the only document",
         );
-        self.dicts.remove(self.idx);
-        if self.idx == self.dicts.len() {
+        self.docs.remove(self.idx);
+        if self.idx == self.docs.len() {
             self.idx -= 1;
         }
     }
 
-    fn show_all(&mut self, ui: &mut egui::Ui) {
-        for idx in 0..self.dicts.len() {
-            ui.radio_value(&mut self.idx, idx, &self.dicts[idx].name);
+    fn write_project(&self, buf: &mut impl io::Write) -> io::Result<()> {
+        for doc in &self.docs {
+            let dict = doc.dict.to_string();
+            let mut record = vec!["doc", dict.as_str(), doc.operation.to_key()];
+            if let Some(name) = &doc.name {
+                record.push(name);
+            }
+            write_record(buf, &record)?;
+            write_record(buf, &["input", &doc.input])?;
+            write_record(buf, &["output", &doc.output])?;
         }
+        write_record(buf, &["selected-doc", &self.idx.to_string()])
     }
 
-    fn selected(&self) -> &jieba::Jieba {
-        &self
-            .dicts
+    fn selected(&self) -> &Doc {
+        self.docs
             .get(self.idx)
             .expect("cannot be `None`; must have maintained the invariants")
-            .jieba
     }
 
-    fn selected_mut(&mut self) -> &mut jieba::Jieba {
-        &mut self
-            .dicts
+    fn selected_mut(&mut self) -> &mut Doc {
+        self.docs
             .get_mut(self.idx)
             .expect("cannot be `None`; must have maintained the invariants")
-            .jieba
     }
 }
 
-impl From<&DictName> for egui::WidgetText {
-    fn from(val: &DictName) -> Self {
-        match val {
-            DictName::Embedded(kind) => kind.to_name().into(),
-            DictName::File(name) => name.into(),
+impl Doc {
+    fn to_name(&self, idx: usize) -> String {
+        match &self.name {
+            Some(name) => name.clone(),
+            None => String::from(t!("doc.name", number = idx + 1)),
         }
     }
 }
 
-impl Embedded {
-    fn to_name(self) -> impl Into<egui::WidgetText> {
-        match self {
-            Self::Normal => t!("dict.name"),
-            Self::Small => t!("dict.small.name"),
-            Self::Big => t!("dict.big.name"),
+impl Token {
+    // Move the input range by `base` chars, as from a chunk of a file.
+    fn shift(mut self, base: usize) -> Self {
+        self.input = self.input.start + base..self.input.end + base;
+        self
+    }
+}
+
+impl<'a> From<jieba::Token<'a>> for Token {
+    fn from(jieba::Token { word, start, end }: jieba::Token<'a>) -> Self {
+        Token {
+            word: String::from(word),
+            tag: String::new(),
+            input: start..end,
         }
     }
 }
 
-impl ErrorDialog {
-    fn show(&mut self, ui: &mut egui::Ui) -> egui::ModalResponse<()> {
-        egui::Modal::new(self.id).show(ui, |ui| {
-            ui.heading(t!("error-dialog.heading", what = t!(&self.what)));
-            ui.label(&self.content);
+impl CopyFormat {
+    fn to_key(self) -> &'static str {
+        match self {
+            Self::Text => "copy.format.text",
+            Self::Lines => "copy.format.lines",
+            Self::Json => "copy.format.json",
+        }
+    }
+
+    fn to_name(self) -> String {
+        String::from(match self {
+            Self::Text => t!("copy.format.text.name"),
+            Self::Lines => t!("copy.format.lines.name"),
+            Self::Json => t!("copy.format.json.name"),
         })
     }
-}
 
-const FONT_NAME: &str = "noto-sans-cjk";
-fn make_cjk_font_defs() -> egui::FontDefinitions {
-    let mut fonts = egui::FontDefinitions::empty();
-    fonts.font_data.insert(
-        String::from(FONT_NAME),
-        sync::Arc::new(egui::FontData::from_static(include_bytes!(
-            "../fonts/NotoSansCJKsc-Regular.otf"
-        ))),
-    );
-    fonts
-        .families
-        .get_mut(&egui::FontFamily::Proportional)
-        .expect("cannot be `None`; must have inserted the font")
-        .insert(0, String::from(FONT_NAME));
-    fonts
-}
+    // The output of a document read from a project has no items until
+    // it is run again.
+    fn can_render(self, doc: &Doc) -> bool {
+        self == Self::Text || doc.output.is_empty() || !doc.items.is_empty()
+    }
 
-fn make_dict_static(kind: Embedded, bytes: &'static [u8]) -> Dict {
-    Dict {
-        name: DictName::Embedded(kind),
-        jieba: jieba::Jieba::with_dict(&mut io::BufReader::new(bytes))
-            .expect("cannot be `Err(_)`; must have provided a valid static dict"),
+    fn render(self, doc: &Doc) -> String {
+        match self {
+            Self::Text => doc.output.clone(),
+            Self::Lines => {
+                let items: Vec<_> = doc.items.iter().map(|item| &*item.text).collect();
+                items.join("\n")
+            }
+            Self::Json => {
+                let items: Vec<_> = doc
+                    .items
+                    .iter()
+                    .map(|item| quote_json(&item.text))
+                    .collect();
+                format!("[{items}]", items = items.join(","))
+            }
+        }
     }
 }
 
-fn with_pick_file(func: impl FnOnce(path::PathBuf) -> Result<()>) -> Result<()> {
-    match rfd::FileDialog::new().pick_file() {
-        Some(path) => func(path),
-        None => Ok(()),
+const CLIPBOARD_INTERVAL: time::Duration = time::Duration::from_millis(500);
+impl Clipboard {
+    fn get(&mut self) -> Result<&mut arboard::Clipboard> {
+        if self.inner.is_none() {
+            self.inner = Some(arboard::Clipboard::new()?);
+        }
+        Ok(self
+            .inner
+            .as_mut()
+            .expect("cannot be `None`; must have opened the clipboard"))
     }
-}
 
-fn with_save_file(func: impl FnOnce(path::PathBuf) -> Result<()>) -> Result<()> {
-    match rfd::FileDialog::new().save_file() {
-        Some(path) => func(path),
-        None => Ok(()),
+    fn get_text(&mut self) -> Result<String> {
+        Ok(self.get()?.get_text()?)
     }
-}
 
-fn with_out_files(mut func: impl FnMut(&str) -> String) -> Result<()> {
-    let Some(in_paths) = rfd::FileDialog::new().pick_files() else {
-        return Ok(());
-    };
-    let Some(save_path) = rfd::FileDialog::new().pick_folder() else {
-        return Ok(());
-    };
-    for in_path in in_paths {
-        let out_path = save_path.join(
-            in_path
-                .file_name()
-                .expect("cannot be `None`; must be a regular file"),
-        );
-        let input = fs::read_to_string(in_path)?;
-        let input = input.trim();
-        let mut out_file = fs::File::create_new(out_path)?;
-        writeln!(&mut out_file, "{out}", out = func(input))?;
+    fn set_text(&mut self, text: String) -> Result<()> {
+        self.get()?.set_text(text.as_str())?;
+        self.copied = Some(text);
+        Ok(())
+    }
+
+    // Return newly copied text with Chinese characters while watching,
+    // starting or stopping the watcher as toggled.  Text copied by this
+    // program is ignored.
+    fn poll(&mut self, ctx: &egui::Context) -> Option<String> {
+        if !self.watch {
+            self.watcher = None;
+            return None;
+        }
+        let watcher = self
+            .watcher
+            .get_or_insert_with(|| ClipboardWatcher::spawn(ctx.clone()));
+        let text = watcher.texts.try_iter().last()?;
+        (self.copied.as_ref() != Some(&text)).then_some(text)
     }
-    Ok(())
 }
 
-#[cfg(test)]
+impl ClipboardWatcher {
+    // Text copied before watching is ignored.
+    fn spawn(ctx: egui::Context) -> Self {
+        let (sender, texts) = mpsc::channel();
+        let (stop, stopped) = mpsc::channel();
+        thread::spawn(move || {
+            let Ok(mut clipboard) = arboard::Clipboard::new() else {
+                return;
+            };
+            let mut seen = clipboard.get_text().ok();
+            while let Err(mpsc::RecvTimeoutError::Timeout) =
+                stopped.recv_timeout(CLIPBOARD_INTERVAL)
+            {
+                let Ok(text) = clipboard.get_text() else {
+                    continue;
+                };
+                if seen.as_ref() == Some(&text) {
+                    continue;
+                }
+                seen = Some(text.clone());
+                if has_han(&text) {
+                    if sender.send(text).is_err() {
+                        break;
+                    }
+                    ctx.request_repaint();
+                }
+            }
+        });
+        ClipboardWatcher { texts, stop }
+    }
+}
+
+impl Drop for ClipboardWatcher {
+    fn drop(&mut self) {
+        // The thread is gone if it failed to open the clipboard.
+        let _ = self.stop.send(());
+    }
+}
+
+impl Operation {
+    fn to_key(self) -> &'static str {
+        match self {
+            Self::Segment => "segment",
+            Self::SegmentGranular => "segment-granular",
+            Self::Search => "search",
+            Self::Tag => "tag",
+        }
+    }
+
+    fn from_key(key: &str) -> Option<Self> {
+        OPERATIONS
+            .into_iter()
+            .find(|operation| operation.to_key() == key)
+    }
+
+    fn to_text(self) -> String {
+        let key = format!("{key}.text", key = self.to_key());
+        String::from(t!(&key))
+    }
+
+    fn to_hover(self) -> String {
+        let key = format!("{key}.hover", key = self.to_key());
+        String::from(t!(&key))
+    }
+
+    fn to_what(self) -> String {
+        format!("{key}.what", key = self.to_key())
+    }
+
+    fn to_template(self) -> &'static str {
+        match self {
+            Self::Segment | Self::SegmentGranular | Self::Search => "{word}",
+            Self::Tag => "{word} {tag}",
+        }
+    }
+}
+
+impl Command {
+    fn to_key(self) -> &'static str {
+        match self {
+            Self::Palette => "command-palette",
+            Self::OpenProject => "open-project",
+            Self::SaveProject => "save-project",
+            Self::UseHmm => "use-hmm",
+            Self::BatchMode => "batch-mode",
+            Self::NewDict => "new-dict",
+            Self::LoadDict => "load-dict",
+            Self::ExportDict => "export-dict",
+            Self::RemoveDict => "remove-dict",
+            Self::RenameDict => "rename-dict",
+            Self::DuplicateDict => "duplicate-dict",
+            Self::MoveUpDict => "move-up-dict",
+            Self::MoveDownDict => "move-down-dict",
+            Self::LoadHmm => "load-hmm",
+            Self::TrainHmm => "train-hmm",
+            Self::ExportHmm => "export-hmm",
+            Self::ResetHmm => "reset-hmm",
+            Self::AddWord => "add-word",
+            Self::DeleteWord => "delete-word",
+            Self::DisableWord => "disable-word",
+            Self::Undo => "undo",
+            Self::Redo => "redo",
+            Self::NewDoc => "new-doc",
+            Self::CloseDoc => "close-doc",
+            Self::Import => "import",
+            Self::PasteAndRun => "paste-and-run",
+            Self::WatchClipboard => "watch-clipboard",
+            Self::Export => "export",
+            Self::RerunBatch => "rerun-batch",
+            Self::Copy(format) => format.to_key(),
+            Self::Run(operation) => operation.to_key(),
+        }
+    }
+
+    fn from_key(key: &str) -> Option<Self> {
+        COMMANDS.into_iter().find(|command| command.to_key() == key)
+    }
+
+    fn to_name(self) -> String {
+        let key = format!("{key}.command", key = self.to_key());
+        String::from(t!(&key))
+    }
+
+    // Every word in `query` must occur in the name, ignoring case.
+    fn matches(self, query: &str) -> bool {
+        let name = self.to_name().to_lowercase();
+        query
+            .to_lowercase()
+            .split_whitespace()
+            .all(|word| name.contains(word))
+    }
+}
+
+impl Shortcuts {
+    fn get(&self, command: Command) -> Option<egui::KeyboardShortcut> {
+        self.bindings
+            .iter()
+            .find(|&&(other, _)| other == command)
+            .map(|&(_, shortcut)| shortcut)
+    }
+
+    // Bind `command` to `shortcut`, taking it away from any other command.
+    fn set(&mut self, command: Command, shortcut: Option<egui::KeyboardShortcut>) {
+        self.bindings.retain(|&(other, other_shortcut)| {
+            other != command && Some(other_shortcut) != shortcut
+        });
+        if let Some(shortcut) = shortcut {
+            self.bindings.push((command, shortcut));
+        }
+        self.bindings.sort_by_key(|(_, shortcut)| {
+            let egui::Modifiers {
+                alt,
+                ctrl,
+                shift,
+                command,
+                ..
+            } = shortcut.modifiers;
+            cmp::Reverse(
+                [alt, ctrl, shift, command]
+                    .into_iter()
+                    .filter(|&on| on)
+                    .count(),
+            )
+        });
+    }
+
+    fn consume(&self, input: &mut egui::InputState) -> Option<Command> {
+        self.bindings
+            .iter()
+            .find(|(_, shortcut)| input.consume_shortcut(shortcut))
+            .map(|&(command, _)| command)
+    }
+
+    fn write_preferences(&self, buf: &mut impl io::Write) -> io::Result<()> {
+        for command in COMMANDS {
+            let shortcut = self.get(command).map(format_shortcut).unwrap_or_default();
+            write_record(buf, &["shortcut", command.to_key(), &shortcut])?;
+        }
+        Ok(())
+    }
+}
+
+impl CommandPalette {
+    fn show(
+        &mut self,
+        ui: &mut egui::Ui,
+        shortcuts: &mut Shortcuts,
+        enabled: &[Command],
+    ) -> egui::ModalResponse<Option<Command>> {
+        egui::Modal::new(self.id).show(ui, |ui| {
+            self.record(ui, shortcuts);
+            ui.heading(t!("command-palette.command"));
+            let search = ui.add(
+                egui::TextEdit::singleline(&mut self.query).hint_text(t!("command-palette.search")),
+            );
+            if mem::take(&mut self.focus) {
+                search.request_focus();
+            }
+            let commands: Vec<_> = COMMANDS
+                .into_iter()
+                .filter(|command| command.matches(&self.query))
+                .collect();
+            let mut chosen = None;
+            if search.lost_focus() && ui.input(|input| input.key_pressed(egui::Key::Enter)) {
+                chosen = commands
+                    .iter()
+                    .copied()
+                    .find(|command| enabled.contains(command));
+            }
+            ui.separator();
+            egui::ScrollArea::vertical()
+                .max_height(ui.available_height() / 2.0)
+                .show(ui, |ui| {
+                    egui::Grid::new("commands").show(ui, |ui| {
+                        for command in commands {
+                            if ui
+                                .add_enabled(
+                                    enabled.contains(&command),
+                                    egui::Button::new(command.to_name()),
+                                )
+                                .clicked()
+                            {
+                                chosen = Some(command);
+                            }
+                            let text = if self.recording == Some(command) {
+                                String::from(t!("command-palette.recording"))
+                            } else {
+                                shortcuts.get(command).map_or_else(
+                                    || String::from(t!("command-palette.unbound")),
+                                    |shortcut| ui.ctx().format_shortcut(&shortcut),
+                                )
+                            };
+                            if ui
+                                .button(text)
+                                .on_hover_text(t!("command-palette.shortcut"))
+                                .clicked()
+                            {
+                                self.recording = Some(command);
+                            }
+                            ui.end_row();
+                        }
+                    });
+                });
+            chosen
+        })
+    }
+
+    // Bind the next key pressed to the command being recorded, where
+    // Backspace and Delete unbind it, Escape cancels and other keys
+    // that `can_bind` rejects are ignored.
+    fn record(&mut self, ui: &egui::Ui, shortcuts: &mut Shortcuts) {
+        let Some(command) = self.recording else {
+            return;
+        };
+        let Some(shortcut) = ui.input_mut(|input| {
+            let shortcut = input.events.iter().find_map(|event| match event {
+                egui::Event::Key {
+                    key,
+                    pressed: true,
+                    modifiers,
+                    ..
+                } => Some(egui::KeyboardShortcut::new(
+                    normalize_modifiers(*modifiers),
+                    *key,
+                )),
+                _ => None,
+            })?;
+            input.consume_shortcut(&shortcut);
+            Some(shortcut)
+        }) else {
+            return;
+        };
+        match shortcut {
+            egui::KeyboardShortcut {
+                logical_key: egui::Key::Escape,
+                ..
+            } => {}
+            egui::KeyboardShortcut {
+                modifiers: egui::Modifiers::NONE,
+                logical_key: egui::Key::Backspace | egui::Key::Delete,
+            } => shortcuts.set(command, None),
+            _ if !can_bind(shortcut) => return,
+            _ => shortcuts.set(command, Some(shortcut)),
+        }
+        self.recording = None;
+    }
+}
+
+impl From<Theme> for egui::ThemePreference {
+    fn from(val: Theme) -> Self {
+        match val {
+            Theme::System => Self::System,
+            Theme::Light => Self::Light,
+            Theme::Dark => Self::Dark,
+        }
+    }
+}
+
+impl Theme {
+    fn to_name(self) -> impl Into<egui::WidgetText> {
+        match self {
+            Self::System => t!("theme.system.name"),
+            Self::Light => t!("theme.light.name"),
+            Self::Dark => t!("theme.dark.name"),
+        }
+    }
+}
+
+impl Locale {
+    fn to_locale(self) -> &'static str {
+        match self {
+            Self::En => "en",
+            Self::ZhCn => "zh-CN",
+            Self::ZhHk => "zh-HK",
+        }
+    }
+
+    fn to_name(self) -> &'static str {
+        match self {
+            Self::En => "English",
+            Self::ZhCn => "中文（简体）",
+            Self::ZhHk => "中文（繁體）",
+        }
+    }
+}
+
+impl Dicts {
+    fn new_dict(&mut self, name: impl Into<String>, words: Words) {
+        let name = name.into();
+        let dict = Dict::new(DictName::Custom(name.clone()), words);
+        self.insert_dict("history.new-dict", name, dict);
+    }
+
+    fn load_dict(&mut self, name: impl Into<String>, words: Words) {
+        let name = name.into();
+        self.edit("history.load-dict", name.clone(), |dict| {
+            Some(dict.push_layer(name, words))
+        });
+    }
+
+    fn load_hmm(&mut self, idx: usize, key: &'static str, hmm: Hmm) {
+        let name = hmm.name.clone();
+        self.edit_at(idx, key, name, |dict| {
+            dict.set_hmm(Some(sync::Arc::new(hmm)))
+        });
+    }
+
+    fn can_reset_hmm(&self) -> bool {
+        self.selected_dict().hmm.is_some()
+    }
+
+    fn reset_hmm(&mut self) {
+        assert!(
+            self.can_reset_hmm(),
+            "must not trigger this action for built-in HMM",
+        );
+        let name = self.selected_dict().name.to_name();
+        self.edit("history.reset-hmm", name, |dict| dict.set_hmm(None));
+    }
+
+    fn export_dict(&self, buf: &mut impl io::Write, format: Format) -> io::Result<()> {
+        self.selected_dict().write_words(buf, format)
+    }
+
+    fn add_word(&mut self, word: &str, freq: &str, tag: &str) -> Result<()> {
+        let freq = if freq.is_empty() {
+            None
+        } else {
+            Some(freq.parse()?)
+        };
+        let tag = if tag.is_empty() { None } else { Some(tag) };
+        self.edit("history.add-word", word, |dict| {
+            dict.add_word(word, freq, tag)
+        });
+        Ok(())
+    }
+
+    fn has_word(&self, word: &str) -> bool {
+        self.selected_dict().has_word(word)
+    }
+
+    fn is_disabled(&self, word: &str) -> bool {
+        self.selected_dict().disabled.contains(word)
+    }
+
+    fn delete_word(&mut self, word: &str) {
+        self.edit("history.delete-word", word, |dict| dict.delete_word(word));
+    }
+
+    fn disable_word(&mut self, word: &str) {
+        self.edit("history.disable-word", word, |dict| dict.disable_word(word));
+    }
+
+    fn enable_word(&mut self, word: &str) {
+        self.edit("history.enable-word", word, |dict| dict.enable_word(word));
+    }
+
+    fn toggle_layer(&mut self, idx: usize) {
+        let name = self.selected_dict().layers[idx].name.clone();
+        self.edit("history.toggle-layer", name, |dict| {
+            Some(dict.toggle_layer(idx))
+        });
+    }
+
+    fn remove_layer(&mut self, idx: usize) {
+        let name = self.selected_dict().layers[idx].name.clone();
+        self.edit("history.remove-layer", name, |dict| {
+            Some(dict.remove_layer(idx))
+        });
+    }
+
+    fn rename_dict(&mut self, name: impl Into<String>) {
+        let name = name.into();
+        self.edit("history.rename-dict", name.clone(), |dict| {
+            dict.rename(DictName::Custom(name))
+        });
+    }
+
+    fn duplicate_dict(&mut self) {
+        let mut dict = self.selected_dict().clone();
+        let name = String::from(t!("duplicate-dict.name", name = dict.name.to_name()));
+        dict.name = DictName::Custom(name.clone());
+        self.insert_dict("history.duplicate-dict", name, dict);
+    }
+
+    fn insert_dict(&mut self, key: &'static str, arg: String, dict: Dict) {
+        let idx = self.slots.len();
+        let id = self.next_id;
+        self.next_id += 1;
+        let snapshot = dict.to_snapshot();
+        self.insert_slot(idx, id, dict);
+        let change = Change::Insert {
+            idx,
+            id,
+            dict: snapshot,
+        };
+        self.record(key, arg, change);
+    }
+
+    // Prefer the pending dictionary so that a document keeps its choice while loading.
+    fn selected_id(&self) -> usize {
+        self.ids[self.pending.unwrap_or(self.idx)]
+    }
+
+    fn position(&self, id: usize) -> Option<usize> {
+        self.ids.iter().position(|&other| other == id)
+    }
+
+    // Keep the selection if the dictionary is already removed.
+    fn select_id(&mut self, id: usize) {
+        if let Some(idx) = self.position(id) {
+            self.select(idx);
+        }
+    }
+
+    fn can_move_up(&self) -> bool {
+        self.idx != 0
+    }
+
+    fn move_up(&mut self) {
+        assert!(
+            self.can_move_up(),
+            "must not trigger this action for the first dictionary",
+        );
+        self.swap_dict("history.move-up-dict", self.idx - 1);
+    }
+
+    fn can_move_down(&self) -> bool {
+        self.idx != self.slots.len() - 1
+    }
+
+    fn move_down(&mut self) {
+        assert!(
+            self.can_move_down(),
+            "must not trigger this action for the last dictionary",
+        );
+        self.swap_dict("history.move-down-dict", self.idx + 1);
+    }
+
+    fn swap_dict(&mut self, key: &'static str, other: usize) {
+        let name = self.selected_dict().name.to_name();
+        let idx = self.idx;
+        self.swap_slots(idx, other);
+        self.record(key, name, Change::Swap { idx, other });
+    }
+
+    fn can_remove_dict(&self) -> bool {
+        self.slots.len() != 1
+    }
+
+    fn remove_dict(&mut self) {
+        assert!(
+            self.can_remove_dict(),
+            "must not trigger this action for the only dictionary",
+        );
+        let idx = self.idx;
+        let id = self.ids[idx];
+        let dict = self.selected_dict().to_snapshot();
+        let name = dict.name.to_name();
+        self.remove_slot(idx);
+        self.record(
+            "history.remove-dict",
+            name,
+            Change::Remove { idx, id, dict },
+        );
+    }
+
+    // Run `func` on the selected dictionary as an undoable change,
+    // where `func` returns the inverse of its edit, or `None` if it
+    // changes nothing.
+    fn edit(
+        &mut self,
+        key: &'static str,
+        arg: impl Into<String>,
+        func: impl FnOnce(&mut Dict) -> Option<Delta>,
+    ) {
+        self.edit_at(self.idx, key, arg, func);
+    }
+
+    // As `edit`, for the dictionary at `idx`, which must be loaded.
+    fn edit_at(
+        &mut self,
+        idx: usize,
+        key: &'static str,
+        arg: impl Into<String>,
+        func: impl FnOnce(&mut Dict) -> Option<Delta>,
+    ) {
+        let dict = self.slots[idx]
+            .loaded_mut()
+            .expect("cannot be `None`; must have loaded the dictionary to edit");
+        if let Some(delta) = func(dict) {
+            self.record(key, arg.into(), Change::Edit { idx, delta });
+        }
+    }
+
+    fn record(&mut self, key: &'static str, arg: String, change: Change) {
+        if self.history.len() == HISTORY_LIMIT {
+            self.history.pop_front();
+        }
+        self.history.push_back(Record { key, arg, change });
+        self.future.clear();
+    }
+
+    fn can_undo(&self) -> bool {
+        !self.history.is_empty()
+    }
+
+    fn undo(&mut self) {
+        let record = self
+            .history
+            .pop_back()
+            .expect("must not trigger this action for empty history");
+        let record = self.apply(record);
+        self.future.push(record);
+    }
+
+    fn can_redo(&self) -> bool {
+        !self.future.is_empty()
+    }
+
+    fn redo(&mut self) {
+        let record = self
+            .future
+            .pop()
+            .expect("must not trigger this action for empty future");
+        let record = self.apply(record);
+        self.history.push_back(record);
+    }
+
+    // Revert `record` and return its inverse.
+    fn apply(&mut self, record: Record) -> Record {
+        let Record { key, arg, change } = record;
+        let change = match change {
+            Change::Edit { idx, delta } => {
+                let delta = self.slots[idx]
+                    .loaded_mut()
+                    .expect("cannot be `None`; must have kept edited dictionaries loaded")
+                    .apply(delta)
+                    .expect("cannot be `None`; must have recorded only effective edits");
+                self.select(idx);
+                Change::Edit { idx, delta }
+            }
+            Change::Insert { idx, id, dict } => {
+                self.remove_slot(idx);
+                Change::Remove { idx, id, dict }
+            }
+            Change::Remove { idx, id, dict } => {
+                self.insert_slot(idx, id, dict.clone().restore());
+                Change::Insert { idx, id, dict }
+            }
+            Change::Swap { idx, other } => {
+                self.swap_slots(idx, other);
+                Change::Swap {
+                    idx: other,
+                    other: idx,
+                }
+            }
+        };
+        Record { key, arg, change }
+    }
+
+    fn insert_slot(&mut self, idx: usize, id: usize, dict: Dict) {
+        self.slots.insert(idx, Slot::Loaded(dict));
+        self.ids.insert(idx, id);
+        if self.idx >= idx {
+            self.idx += 1;
+        }
+        self.pending = self
+            .pending
+            .map(|pending| if pending >= idx { pending + 1 } else { pending });
+    }
+
+    fn remove_slot(&mut self, idx: usize) {
+        self.slots.remove(idx);
+        self.ids.remove(idx);
+        self.pending = match self.pending {
+            Some(pending) if pending > idx => Some(pending - 1),
+            pending => pending.filter(|&pending| pending != idx),
+        };
+        if self.idx > idx || self.idx == self.slots.len() {
+            self.idx -= 1;
+        }
+        if self.slots[self.idx].loaded().is_some() {
+            return;
+        }
+        // The nearest loaded dictionary stands in until the next one is
+        // loaded in the background, unless there is none left.
+        let next = self.idx;
+        let nearest = (0..self.slots.len())
+            .filter(|&other| self.slots[other].loaded().is_some())
+            .min_by_key(|&other| other.abs_diff(next));
+        match nearest {
+            Some(nearest) => {
+                self.idx = nearest;
+                self.select(next);
+            }
+            None => self.load_now(next),
+        }
+    }
+
+    fn swap_slots(&mut self, idx: usize, other: usize) {
+        self.slots.swap(idx, other);
+        self.ids.swap(idx, other);
+        for current in iter::once(&mut self.idx).chain(&mut self.pending) {
+            if *current == idx {
+                *current = other;
+            } else if *current == other {
+                *current = idx;
+            }
+        }
+    }
+
+    fn select(&mut self, idx: usize) {
+        match &self.slots[idx] {
+            Slot::Loaded(_) => {
+                self.idx = idx;
+                self.pending = None;
+            }
+            Slot::Unloaded(kind) => {
+                let kind = *kind;
+                let (sender, receiver) = mpsc::channel();
+                thread::spawn(move || {
+                    // The receiver is gone only if the slot is removed.
+                    let _ = sender.send(kind.load());
+                });
+                self.slots[idx] = Slot::Loading(kind, receiver);
+                self.pending = Some(idx);
+            }
+            Slot::Loading(..) => self.pending = Some(idx),
+        }
+    }
+
+    fn load_now(&mut self, idx: usize) {
+        let dict = match &self.slots[idx] {
+            Slot::Loaded(_) => return,
+            Slot::Unloaded(kind) => kind.load(),
+            Slot::Loading(kind, receiver) => receiver.recv().unwrap_or_else(|_| kind.load()),
+        };
+        self.slots[idx] = Slot::Loaded(dict);
+    }
+
+    // Block until the pending dictionary, if any, is selected.
+    fn wait_pending(&mut self) {
+        if let Some(idx) = self.pending {
+            self.load_now(idx);
+            self.select(idx);
+        }
+    }
+
+    fn poll_loading(&mut self) {
+        for idx in 0..self.slots.len() {
+            if let Slot::Loading(kind, receiver) = &self.slots[idx] {
+                self.slots[idx] = match receiver.try_recv() {
+                    Ok(dict) => Slot::Loaded(dict),
+                    Err(mpsc::TryRecvError::Empty) => continue,
+                    Err(mpsc::TryRecvError::Disconnected) => Slot::Unloaded(*kind),
+                };
+                if self.pending == Some(idx) && matches!(self.slots[idx], Slot::Loaded(_)) {
+                    self.idx = idx;
+                    self.pending = None;
+                }
+            }
+        }
+    }
+
+    fn show_all(&mut self, ui: &mut egui::Ui) {
+        self.poll_loading();
+        for idx in 0..self.slots.len() {
+            ui.horizontal(|ui| {
+                if ui.radio(self.idx == idx, &self.slots[idx]).clicked() {
+                    self.select(idx);
+                }
+                if let Slot::Loading(..) = self.slots[idx] {
+                    ui.spinner().on_hover_text(t!("loading-dict.hover"));
+                }
+            });
+        }
+    }
+
+    fn show_layers(&mut self, ui: &mut egui::Ui) {
+        let mut toggled = None;
+        let mut removed = None;
+        for (idx, layer) in self.selected_dict().layers.iter().enumerate() {
+            ui.horizontal(|ui| {
+                let mut enabled = layer.enabled;
+                if ui
+                    .checkbox(&mut enabled, &layer.name)
+                    .on_hover_text(t!("layer.hover"))
+                    .changed()
+                {
+                    toggled = Some(idx);
+                }
+                if ui
+                    .small_button(t!("remove-layer.text"))
+                    .on_hover_text(t!("remove-layer.hover"))
+                    .clicked()
+                {
+                    removed = Some(idx);
+                }
+            });
+        }
+        if let Some(idx) = toggled {
+            self.toggle_layer(idx);
+        }
+        if let Some(idx) = removed {
+            self.remove_layer(idx);
+        }
+    }
+
+    // Clicking a record undoes or redoes until right after it.
+    fn show_history(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            if ui
+                .add_enabled(self.can_undo(), egui::Button::new(t!("undo.text")))
+                .on_hover_text(t!("undo.hover"))
+                .clicked()
+            {
+                self.undo();
+            }
+            if ui
+                .add_enabled(self.can_redo(), egui::Button::new(t!("redo.text")))
+                .on_hover_text(t!("redo.hover"))
+                .clicked()
+            {
+                self.redo();
+            }
+        });
+        let mut undone = None;
+        let mut redone = None;
+        let len = self.history.len();
+        for (idx, record) in self.history.iter().enumerate() {
+            if ui
+                .selectable_label(idx + 1 == len, record.to_text())
+                .clicked()
+            {
+                undone = Some(len - idx - 1);
+            }
+        }
+        for (idx, record) in self.future.iter().rev().enumerate() {
+            let text = egui::RichText::new(record.to_text()).weak();
+            if ui.selectable_label(false, text).clicked() {
+                redone = Some(idx + 1);
+            }
+        }
+        for _ in 0..undone.unwrap_or_default() {
+            self.undo();
+        }
+        for _ in 0..redone.unwrap_or_default() {
+            self.redo();
+        }
+    }
+
+    fn show_disabled(&mut self, ui: &mut egui::Ui) {
+        let mut enabled = None;
+        for word in &self.selected_dict().disabled {
+            ui.horizontal(|ui| {
+                ui.label(word);
+                if ui
+                    .small_button(t!("enable-word.text"))
+                    .on_hover_text(t!("enable-word.hover"))
+                    .clicked()
+                {
+                    enabled = Some(word.clone());
+                }
+            });
+        }
+        if let Some(word) = enabled {
+            self.enable_word(&word);
+        }
+    }
+
+    fn write_project(&self, buf: &mut impl io::Write) -> io::Result<()> {
+        for (slot, id) in self.slots.iter().zip(&self.ids) {
+            let id = id.to_string();
+            match slot {
+                Slot::Loaded(dict) => dict.write_project(buf, &id)?,
+                Slot::Unloaded(kind) | Slot::Loading(kind, _) => {
+                    write_record(buf, &["unloaded", &id, kind.to_key()])?;
+                }
+            }
+        }
+        write_record(buf, &["selected-dict", &self.idx.to_string()])
+    }
+
+    fn selected(&self) -> &jieba::Jieba {
+        &self.selected_dict().jieba
+    }
+
+    fn selected_dict(&self) -> &Dict {
+        self.slots[self.idx]
+            .loaded()
+            .expect("cannot be `None`; must have loaded the selected dictionary")
+    }
+}
+
+impl Record {
+    fn to_text(&self) -> String {
+        String::from(t!(self.key, name = self.arg))
+    }
+}
+
+impl Snapshot {
+    fn restore(self) -> Dict {
+        let Snapshot {
+            name,
+            base,
+            layers,
+            words,
+            disabled,
+            hmm,
+        } = self;
+        let mut dict = Dict {
+            name,
+            base,
+            layers,
+            words,
+            disabled,
+            hmm,
+            jieba: sync::Arc::new(jieba::Jieba::empty()),
+            fingerprint: sync::OnceLock::new(),
+        };
+        dict.rebuild();
+        dict
+    }
+}
+
+impl Dict {
+    fn new(name: DictName, base: impl Into<sync::Arc<Words>>) -> Self {
+        let mut dict = Dict {
+            name,
+            base: base.into(),
+            layers: Vec::new(),
+            words: Words::new(),
+            disabled: collections::BTreeSet::new(),
+            hmm: None,
+            jieba: sync::Arc::new(jieba::Jieba::empty()),
+            fingerprint: sync::OnceLock::new(),
+        };
+        dict.rebuild();
+        dict
+    }
+
+    fn apply(&mut self, delta: Delta) -> Option<Delta> {
+        match delta {
+            Delta::Word(word, state) => self.set_word(&word, state),
+            Delta::InsertLayer(idx, layer, disabled) => {
+                Some(self.insert_layer(idx, layer, disabled))
+            }
+            Delta::RemoveLayer(idx) => Some(self.remove_layer(idx)),
+            Delta::ToggleLayer(idx) => Some(self.toggle_layer(idx)),
+            Delta::Rename(name) => self.rename(name),
+            Delta::Hmm(hmm) => self.set_hmm(hmm),
+        }
+    }
+
+    fn push_layer(&mut self, name: String, words: Words) -> Delta {
+        let layer = Layer {
+            name,
+            words,
+            enabled: true,
+        };
+        self.insert_layer(self.layers.len(), layer, collections::BTreeSet::new())
+    }
+
+    // `disabled` are the words of `layer` to disable again.
+    fn insert_layer(
+        &mut self,
+        idx: usize,
+        layer: Layer,
+        disabled: collections::BTreeSet<String>,
+    ) -> Delta {
+        self.disabled.extend(disabled);
+        let enabled = layer.enabled;
+        if enabled && idx == self.layers.len() {
+            self.fingerprint.take();
+            let jieba = sync::Arc::make_mut(&mut self.jieba);
+            for (word, Entry { freq, tag }) in &layer.words {
+                if !(self.disabled.contains(word) || self.words.contains_key(word)) {
+                    jieba.add_word(word, Some(*freq), Some(tag));
+                }
+            }
+            self.layers.push(layer);
+        } else {
+            self.layers.insert(idx, layer);
+            if enabled {
+                self.rebuild();
+            }
+        }
+        Delta::RemoveLayer(idx)
+    }
+
+    fn toggle_layer(&mut self, idx: usize) -> Delta {
+        let layer = &mut self.layers[idx];
+        layer.enabled = !layer.enabled;
+        self.rebuild();
+        Delta::ToggleLayer(idx)
+    }
+
+    fn remove_layer(&mut self, idx: usize) -> Delta {
+        let layer = self.layers.remove(idx);
+        let (disabled, removed) = mem::take(&mut self.disabled)
+            .into_iter()
+            .partition(|word| self.has_word(word));
+        self.disabled = disabled;
+        if layer.enabled {
+            self.rebuild();
+        }
+        Delta::InsertLayer(idx, layer, removed)
+    }
+
+    fn rename(&mut self, name: DictName) -> Option<Delta> {
+        if self.name == name {
+            return None;
+        }
+        Some(Delta::Rename(mem::replace(&mut self.name, name)))
+    }
+
+    fn set_hmm(&mut self, hmm: Option<sync::Arc<Hmm>>) -> Option<Delta> {
+        let unchanged = match (&self.hmm, &hmm) {
+            (Some(hmm), Some(other)) => sync::Arc::ptr_eq(hmm, other),
+            (hmm, other) => hmm.is_none() && other.is_none(),
+        };
+        if unchanged {
+            return None;
+        }
+        self.fingerprint.take();
+        Some(Delta::Hmm(mem::replace(&mut self.hmm, hmm)))
+    }
+
+    fn to_snapshot(&self) -> Snapshot {
+        Snapshot {
+            name: self.name.clone(),
+            base: sync::Arc::clone(&self.base),
+            layers: self.layers.clone(),
+            words: self.words.clone(),
+            disabled: self.disabled.clone(),
+            hmm: self.hmm.clone(),
+        }
+    }
+
+    fn has_word(&self, word: &str) -> bool {
+        self.base.contains_key(word)
+            || self
+                .layers
+                .iter()
+                .any(|layer| layer.words.contains_key(word))
+            || self.words.contains_key(word)
+    }
+
+    // An existing word keeps its tag, as in `insert_word`.
+    fn add_word(&mut self, word: &str, freq: Option<usize>, tag: Option<&str>) -> Option<Delta> {
+        let freq = freq.unwrap_or_else(|| self.jieba.suggest_freq(word));
+        let mut state = self.word_state(word);
+        let tag = state
+            .words
+            .take()
+            .map_or_else(|| String::from(tag.unwrap_or_default()), |entry| entry.tag);
+        state.words = Some(Entry { freq, tag });
+        state.disabled = false;
+        self.set_word(word, state)
+    }
+
+    fn delete_word(&mut self, word: &str) -> Option<Delta> {
+        let state = WordState {
+            layers: vec![None; self.layers.len()],
+            ..WordState::default()
+        };
+        self.set_word(word, state)
+    }
+
+    fn disable_word(&mut self, word: &str) -> Option<Delta> {
+        let mut state = self.word_state(word);
+        state.disabled = true;
+        self.set_word(word, state)
+    }
+
+    fn enable_word(&mut self, word: &str) -> Option<Delta> {
+        let mut state = self.word_state(word);
+        state.disabled = false;
+        self.set_word(word, state)
+    }
+
+    fn word_state(&self, word: &str) -> WordState {
+        WordState {
+            base: self.base.get(word).cloned(),
+            layers: self
+                .layers
+                .iter()
+                .map(|layer| layer.words.get(word).cloned())
+                .collect(),
+            words: self.words.get(word).cloned(),
+            disabled: self.disabled.contains(word),
+        }
+    }
+
+    // The base is only copied if it is still shared and the word is in
+    // it.  `jieba::Jieba` is rebuilt only if the word is gone or its tag
+    // changed, since it keeps the first tag of a word.
+    fn set_word(&mut self, word: &str, state: WordState) -> Option<Delta> {
+        let before = self.word_state(word);
+        if before == state {
+            return None;
+        }
+        let merged = self.merged_entry(word);
+        let WordState {
+            base,
+            layers,
+            words,
+            disabled,
+        } = state;
+        if before.base != base {
+            set_entry(sync::Arc::make_mut(&mut self.base), word, base);
+        }
+        for (layer, entry) in iter::zip(&mut self.layers, layers) {
+            set_entry(&mut layer.words, word, entry);
+        }
+        set_entry(&mut self.words, word, words);
+        if disabled {
+            self.disabled.insert(String::from(word));
+        } else {
+            self.disabled.remove(word);
+        }
+        match (merged, self.merged_entry(word)) {
+            (None, None) => {}
+            (merged, Some(Entry { freq, tag }))
+                if merged.as_ref().is_none_or(|merged| merged.tag == tag) =>
+            {
+                sync::Arc::make_mut(&mut self.jieba).add_word(word, Some(freq), Some(&tag));
+                self.fingerprint.take();
+            }
+            _ => self.rebuild(),
+        }
+        Some(Delta::Word(String::from(word), before))
+    }
+
+    // The entry of `word` in `jieba`, if any.
+    fn merged_entry(&self, word: &str) -> Option<Entry> {
+        if self.disabled.contains(word) {
+            return None;
+        }
+        let mut merged = Words::new();
+        for words in self.sources() {
+            if let Some(Entry { freq, tag }) = words.get(word) {
+                insert_word(&mut merged, String::from(word), *freq, tag.clone());
+            }
+        }
+        merged.remove(word)
+    }
+
+    fn sources(&self) -> impl Iterator<Item = &Words> {
+        iter::once(&*self.base)
+            .chain(
+                self.layers
+                    .iter()
+                    .filter(|layer| layer.enabled)
+                    .map(|layer| &layer.words),
+            )
+            .chain(iter::once(&self.words))
+    }
+
+    // `jieba::Jieba` cannot remove words, so start over from the
+    // sources.  It merges duplicated words the same way as
+    // `insert_word`.
+    fn rebuild(&mut self) {
+        let mut jieba = jieba::Jieba::empty();
+        for words in self.sources() {
+            for (word, Entry { freq, tag }) in words {
+                if !self.disabled.contains(word) {
+                    jieba.add_word(word, Some(*freq), Some(tag));
+                }
+            }
+        }
+        self.jieba = sync::Arc::new(jieba);
+        self.fingerprint.take();
+    }
+
+    // Words still shared with an embedded dictionary are referred to
+    // instead of written out.
+    fn write_project(&self, buf: &mut impl io::Write, id: &str) -> io::Result<()> {
+        match &self.name {
+            DictName::Embedded(kind) => {
+                write_record(buf, &["dict", id, "embedded", kind.to_key()])?;
+            }
+            DictName::Custom(name) => write_record(buf, &["dict", id, "custom", name])?,
+        }
+        if let Some(kind) = Embedded::find_shared(&self.base) {
+            write_record(buf, &["base", "embedded", kind.to_key()])?;
+        } else {
+            write_entries(buf, "base-word", &self.base)?;
+        }
+        for layer in &self.layers {
+            write_record(buf, &["layer", &layer.name, &layer.enabled.to_string()])?;
+            write_entries(buf, "layer-word", &layer.words)?;
+        }
+        write_entries(buf, "word", &self.words)?;
+        for word in &self.disabled {
+            write_record(buf, &["disabled", word])?;
+        }
+        if let Some(hmm) = &self.hmm {
+            write_record(buf, &["hmm", &hmm.name, &hmm.to_text()])?;
+        }
+        Ok(())
+    }
+
+    // Hash of the merged words and the HMM, which changes whenever batch
+    // results may.
+    fn fingerprint(&self) -> u64 {
+        *self.fingerprint.get_or_init(|| {
+            let mut hasher = FnvHasher(0xcbf2_9ce4_8422_2325);
+            self.write_words(&mut hasher, Format::Jieba)
+                .expect("cannot be `Err`; hashing never fails");
+            if let Some(hmm) = &self.hmm {
+                hmm.write(&mut hasher)
+                    .expect("cannot be `Err`; hashing never fails");
+            }
+            hasher.0
+        })
+    }
+
+    fn write_words(&self, buf: &mut impl io::Write, format: Format) -> io::Result<()> {
+        let mut merged = Words::new();
+        for words in self.sources() {
+            for (word, Entry { freq, tag }) in words {
+                if !self.disabled.contains(word) {
+                    insert_word(&mut merged, word.clone(), *freq, tag.clone());
+                }
+            }
+        }
+        for (word, entry) in &merged {
+            format.write_line(buf, word, entry)?;
+        }
+        buf.flush()
+    }
+}
+
+impl Slot {
+    fn loaded(&self) -> Option<&Dict> {
+        match self {
+            Self::Loaded(dict) => Some(dict),
+            Self::Unloaded(_) | Self::Loading(..) => None,
+        }
+    }
+
+    fn loaded_mut(&mut self) -> Option<&mut Dict> {
+        match self {
+            Self::Loaded(dict) => Some(dict),
+            Self::Unloaded(_) | Self::Loading(..) => None,
+        }
+    }
+}
+
+impl From<&Slot> for egui::WidgetText {
+    fn from(val: &Slot) -> Self {
+        match val {
+            Slot::Loaded(dict) => (&dict.name).into(),
+            Slot::Unloaded(kind) | Slot::Loading(kind, _) => kind.to_name().into(),
+        }
+    }
+}
+
+impl From<&DictName> for egui::WidgetText {
+    fn from(val: &DictName) -> Self {
+        val.to_name().into()
+    }
+}
+
+impl DictName {
+    fn to_name(&self) -> String {
+        match self {
+            Self::Embedded(kind) => kind.to_name(),
+            Self::Custom(name) => name.clone(),
+        }
+    }
+}
+
+impl Format {
+    fn to_name(self) -> String {
+        String::from(match self {
+            Self::Jieba => t!("format.jieba.name"),
+            Self::Csv => t!("format.csv.name"),
+            Self::Tsv => t!("format.tsv.name"),
+            Self::Plain => t!("format.plain.name"),
+            Self::HanLp => t!("format.hanlp.name"),
+            Self::Pkuseg => t!("format.pkuseg.name"),
+        })
+    }
+
+    // jieba: `word [freq [tag]]`, whitespace-separated;
+    // CSV: `word[,freq[,tag]]`, with double quotes;
+    // TSV: `word[<tab>freq[<tab>tag]]`;
+    // plain: `word`;
+    // HanLP: `word [tag freq]...`, whitespace-separated;
+    // pkuseg: `word[<tab>tag]`.
+    fn split_line(self, line: &str) -> Fields<'_> {
+        let line = line.trim_end_matches(['\r', '\n']);
+        let mut iter: Box<dyn Iterator<Item = borrow::Cow<str>> + '_> = match self {
+            Self::Jieba | Self::HanLp => Box::new(line.split_whitespace().map(borrow::Cow::from)),
+            Self::Csv => Box::new(split_csv(line).into_iter()),
+            Self::Tsv | Self::Pkuseg => Box::new(line.split('\t').map(borrow::Cow::from)),
+            Self::Plain => Box::new(iter::once(borrow::Cow::from(line.trim()))),
+        };
+        let mut next = || iter.next().filter(|field| !field.is_empty());
+        match self {
+            Self::Jieba | Self::Csv | Self::Tsv => Fields {
+                word: next(),
+                freq: next(),
+                tag: next(),
+            },
+            Self::Plain => Fields {
+                word: next(),
+                freq: None,
+                tag: None,
+            },
+            Self::HanLp => {
+                let word = next();
+                let tag = next();
+                let freq = next();
+                Fields { word, freq, tag }
+            }
+            Self::Pkuseg => {
+                let word = next();
+                let tag = next();
+                Fields {
+                    word,
+                    freq: None,
+                    tag,
+                }
+            }
+        }
+    }
+
+    // HanLP and pkuseg come with tag sets of their own, so that any tag is
+    // taken as is.
+    fn knows_tag(self, tag: &str) -> bool {
+        match self {
+            Self::Jieba | Self::Csv | Self::Tsv | Self::Plain => TAGS.contains(&tag),
+            Self::HanLp | Self::Pkuseg => true,
+        }
+    }
+
+    fn write_line(self, buf: &mut impl io::Write, word: &str, entry: &Entry) -> io::Result<()> {
+        let Entry { freq, tag } = entry;
+        match self {
+            Self::Jieba if tag.is_empty() => writeln!(buf, "{word} {freq}"),
+            Self::Jieba => writeln!(buf, "{word} {freq} {tag}"),
+            Self::Csv => {
+                let word = quote_csv(word);
+                let tag = quote_csv(tag);
+                writeln!(buf, "{word},{freq},{tag}")
+            }
+            Self::Tsv => writeln!(buf, "{word}\t{freq}\t{tag}"),
+            // HanLP cannot omit the tag before the frequency.
+            Self::HanLp => {
+                let tag = if tag.is_empty() { "nz" } else { tag };
+                writeln!(buf, "{word} {tag} {freq}")
+            }
+            Self::Pkuseg if !tag.is_empty() => writeln!(buf, "{word}\t{tag}"),
+            Self::Plain | Self::Pkuseg => writeln!(buf, "{word}"),
+        }
+    }
+}
+
+static EMBEDDED_WORDS: [sync::OnceLock<sync::Arc<Words>>; 3] = [const { sync::OnceLock::new() }; 3];
+impl Embedded {
+    fn to_name(self) -> String {
+        String::from(match self {
+            Self::Normal => t!("dict.name"),
+            Self::Small => t!("dict.small.name"),
+            Self::Big => t!("dict.big.name"),
+        })
+    }
+
+    // The table written by the build script.
+    fn to_table(self) -> &'static [u8] {
+        match self {
+            Self::Normal => include_bytes!(concat!(env!("OUT_DIR"), "/dict.txt.bin")),
+            Self::Small => include_bytes!(concat!(env!("OUT_DIR"), "/dict.txt.small.bin")),
+            Self::Big => include_bytes!(concat!(env!("OUT_DIR"), "/dict.txt.big.bin")),
+        }
+    }
+
+    fn to_key(self) -> &'static str {
+        match self {
+            Self::Normal => "normal",
+            Self::Small => "small",
+            Self::Big => "big",
+        }
+    }
+
+    fn from_key(key: &str) -> Option<Self> {
+        EMBEDDEDS.into_iter().find(|kind| kind.to_key() == key)
+    }
+
+    // The words are read once and shared by every dictionary based on
+    // this one.
+    fn words(self) -> sync::Arc<Words> {
+        let words = EMBEDDED_WORDS[self as usize]
+            .get_or_init(|| sync::Arc::new(read_table(self.to_table())));
+        sync::Arc::clone(words)
+    }
+
+    fn find_shared(words: &sync::Arc<Words>) -> Option<Self> {
+        EMBEDDEDS.into_iter().find(|kind| {
+            EMBEDDED_WORDS[*kind as usize]
+                .get()
+                .is_some_and(|shared| sync::Arc::ptr_eq(shared, words))
+        })
+    }
+
+    fn load(self) -> Dict {
+        Dict::new(DictName::Embedded(self), self.words())
+    }
+}
+
+impl CheckDialog {
+    fn show(&mut self, ui: &mut egui::Ui) -> egui::ModalResponse<Option<CheckChoice>> {
+        egui::Modal::new(self.id).show(ui, |ui| {
+            ui.heading(t!("check-dialog.heading", name = &self.name));
+            egui::ScrollArea::vertical()
+                .max_height(ui.available_height() / 2.0)
+                .show(ui, |ui| {
+                    for diagnostic in &self.diagnostics {
+                        ui.label(diagnostic.to_string());
+                    }
+                });
+            ui.separator();
+            ui.horizontal(|ui| {
+                if ui
+                    .button(t!("check-dialog.skip.text"))
+                    .on_hover_text(t!("check-dialog.skip.hover"))
+                    .clicked()
+                {
+                    return Some(CheckChoice::Skip);
+                }
+                if ui
+                    .button(t!("check-dialog.export.text"))
+                    .on_hover_text(t!("check-dialog.export.hover"))
+                    .clicked()
+                {
+                    return Some(CheckChoice::Export);
+                }
+                if ui.button(t!("check-dialog.cancel.text")).clicked() {
+                    return Some(CheckChoice::Cancel);
+                }
+                None
+            })
+            .inner
+        })
+    }
+
+    fn write_report(&self, buf: &mut impl io::Write) -> io::Result<()> {
+        writeln!(buf, "{name}", name = self.name)?;
+        for diagnostic in &self.diagnostics {
+            writeln!(buf, "{diagnostic}")?;
+        }
+        buf.flush()
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let line = self.line_no;
+        let text = match &self.problem {
+            Problem::InvalidUtf8 => t!("check-dialog.invalid-utf8", line = line),
+            Problem::InvalidFreq(freq) => t!("check-dialog.invalid-freq", line = line, freq = freq),
+            Problem::UnknownTag(tag) => t!("check-dialog.unknown-tag", line = line, tag = tag),
+            Problem::Duplicated(word) => t!("check-dialog.duplicated", line = line, word = word),
+        };
+        f.write_str(&text)
+    }
+}
+
+impl Runner {
+    fn segmenter(&self) -> Segmenter<'_> {
+        Segmenter {
+            jieba: &self.jieba,
+            hmm: self.hmm.as_deref(),
+            use_hmm: self.use_hmm,
+        }
+    }
+}
+
+impl<'a> Segmenter<'a> {
+    fn run_tokens(self, operation: Operation, input: &str) -> Vec<Token> {
+        match operation {
+            Operation::Segment => self.segment_tokens(input),
+            Operation::SegmentGranular => self.segment_granular_tokens(input),
+            Operation::Search => self.search_tokens(input),
+            Operation::Tag => self.tag_tokens(input),
+        }
+    }
+
+    fn segment_tokens(self, input: &str) -> Vec<Token> {
+        self.cut(input).into_iter().map(Token::from).collect()
+    }
+
+    fn segment_granular_tokens(self, input: &str) -> Vec<Token> {
+        self.cut_for_search(input)
+            .into_iter()
+            .map(Token::from)
+            .collect()
+    }
+
+    fn search_tokens(self, input: &str) -> Vec<Token> {
+        self.jieba
+            .cut_all(input)
+            .into_iter()
+            .map(Token::from)
+            .collect()
+    }
+
+    // Tagged words cover the input text one after another.
+    fn tag_tokens(self, input: &str) -> Vec<Token> {
+        let mut start = 0;
+        self.tag(input)
+            .into_iter()
+            .map(|(word, tag)| {
+                let end = start + word.chars().count();
+                let token = Token {
+                    word: String::from(word),
+                    tag: String::from(tag),
+                    input: start..end,
+                };
+                start = end;
+                token
+            })
+            .collect()
+    }
+
+    fn cut<'b>(self, input: &'b str) -> Vec<jieba::Token<'b>>
+    where
+        'a: 'b,
+    {
+        match self.hmm {
+            Some(hmm) if self.use_hmm => hmm.cut_tokens(self.jieba, input),
+            _ => self.jieba.cut(input, self.use_hmm),
+        }
+    }
+
+    // As `jieba::Jieba::cut_for_search`, adding the 2- and 3-grams of
+    // longer words that are in the dictionary before the words.
+    fn cut_for_search<'b>(self, input: &'b str) -> Vec<jieba::Token<'b>>
+    where
+        'a: 'b,
+    {
+        if self.hmm.is_none() || !self.use_hmm {
+            return self.jieba.cut_for_search(input, self.use_hmm);
+        }
+        let mut tokens = Vec::new();
+        for token in self.cut(input) {
+            let bounds: Vec<_> = token
+                .word
+                .char_indices()
+                .map(|(idx, _)| idx)
+                .chain(iter::once(token.word.len()))
+                .collect();
+            let len = bounds.len() - 1;
+            for gram in [2, 3] {
+                if len <= gram {
+                    continue;
+                }
+                for idx in 0..=len - gram {
+                    let word = &token.word[bounds[idx]..bounds[idx + gram]];
+                    if self.jieba.has_word(word) {
+                        tokens.push(jieba::Token {
+                            word,
+                            start: token.start + idx,
+                            end: token.start + idx + gram,
+                        });
+                    }
+                }
+            }
+            tokens.push(token);
+        }
+        tokens
+    }
+
+    // As `jieba::Jieba::tag`, which looks up each word cut with HMM.
+    fn tag<'b>(self, input: &'b str) -> Vec<(&'b str, &'b str)>
+    where
+        'a: 'b,
+    {
+        if self.hmm.is_none() || !self.use_hmm {
+            return self
+                .jieba
+                .tag(input, self.use_hmm)
+                .into_iter()
+                .map(|jieba::Tag { word, tag, .. }| (word, tag))
+                .collect();
+        }
+        self.cut(input)
+            .into_iter()
+            .map(
+                |jieba::Token { word, .. }| match self.jieba.tag(word, false)[..] {
+                    [jieba::Tag { tag, .. }] => (word, tag),
+                    // Only runs of Han characters are cut by HMM.
+                    _ => (word, "x"),
+                },
+            )
+            .collect()
+    }
+}
+
+impl io::Write for FnvHasher {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        for &byte in buf {
+            self.0 = (self.0 ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3);
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl ErrorDialog {
+    fn new(what: &str, err: &dyn error::Error) -> Self {
+        Self {
+            id: egui::Id::new("error_dialog"),
+            what: String::from(what),
+            content: err.to_string(),
+        }
+    }
+
+    fn show(&mut self, ui: &mut egui::Ui) -> egui::ModalResponse<()> {
+        egui::Modal::new(self.id).show(ui, |ui| {
+            ui.heading(t!("error-dialog.heading", what = t!(&self.what)));
+            ui.label(&self.content);
+        })
+    }
+}
+
+const FONT_NAME: &str = "noto-sans-cjk";
+fn make_cjk_font_defs() -> egui::FontDefinitions {
+    let mut fonts = egui::FontDefinitions::empty();
+    fonts.font_data.insert(
+        String::from(FONT_NAME),
+        sync::Arc::new(egui::FontData::from_static(include_bytes!(
+            "../fonts/NotoSansCJKsc-Regular.otf"
+        ))),
+    );
+    fonts
+        .families
+        .get_mut(&egui::FontFamily::Proportional)
+        .expect("cannot be `None`; must have inserted the font")
+        .insert(0, String::from(FONT_NAME));
+    fonts
+}
+
+fn or_default<'a>(field: &'a str, default: &'a str) -> &'a str {
+    if field.is_empty() { default } else { field }
+}
+
+// Return the number of line breaks if `word` is only made of them.
+fn count_line_breaks(word: &str) -> Option<usize> {
+    (!word.is_empty() && word.chars().all(|c| matches!(c, '\r' | '\n')))
+        .then(|| word.matches('\n').count())
+}
+
+const LARGE_TEXT_LEN: usize = 1024 * 1024;
+const ROW_LEN: usize = 256;
+// Split `text` into lines, and long lines further into rows of at most
+// `len` characters.
+fn split_rows(text: &str, len: usize) -> Vec<ops::Range<usize>> {
+    let mut rows = Vec::new();
+    let mut start = 0;
+    for line in text.split('\n') {
+        let mut row_start = start;
+        for (idx, _) in line.char_indices().skip(len).step_by(len) {
+            rows.push(row_start..start + idx);
+            row_start = start + idx;
+        }
+        rows.push(row_start..start + line.len());
+        start += line.len() + 1;
+    }
+    rows
+}
+
+// Show only the visible rows of `text`, which is too large to lay out
+// as a whole.
+fn show_rows(ui: &mut egui::Ui, text: &str, rows: &[ops::Range<usize>]) {
+    let row_height = ui.text_style_height(&egui::TextStyle::Body);
+    egui::ScrollArea::both().auto_shrink(false).show_rows(
+        ui,
+        row_height,
+        rows.len(),
+        |ui, range| {
+            for row in &rows[range] {
+                ui.add(egui::Label::new(&text[row.clone()]).extend());
+            }
+        },
+    );
+}
+
+// Lay out `text` as `egui::TextEdit` does, except that the characters in
+// `highlight` are painted over the selection color.
+fn layout_highlighted(
+    ui: &egui::Ui,
+    text: &str,
+    highlight: Option<ops::Range<usize>>,
+    wrap_width: f32,
+) -> sync::Arc<egui::Galley> {
+    let font_id = egui::FontSelection::default().resolve(ui.style());
+    let color = ui
+        .visuals()
+        .override_text_color
+        .unwrap_or_else(|| ui.visuals().widgets.inactive.text_color());
+    let format = egui::TextFormat::simple(font_id, color);
+    let (start, end) = highlight.map_or((text.len(), text.len()), |range| {
+        (
+            get_byte_idx(text, range.start),
+            get_byte_idx(text, range.end),
+        )
+    });
+    let mut job = egui::text::LayoutJob::default();
+    job.wrap.max_width = wrap_width;
+    job.append(&text[..start], 0.0, format.clone());
+    job.append(
+        &text[start..end],
+        0.0,
+        egui::TextFormat {
+            background: ui.visuals().selection.bg_fill,
+            ..format.clone()
+        },
+    );
+    job.append(&text[end..], 0.0, format);
+    ui.fonts_mut(|fonts| fonts.layout_job(job))
+}
+
+fn get_byte_idx(text: &str, char_idx: usize) -> usize {
+    text.char_indices()
+        .nth(char_idx)
+        .map_or(text.len(), |(idx, _)| idx)
+}
+
+// The character index hovered, or else at the cursor while focused.
+fn get_point(output: &egui::text_edit::TextEditOutput) -> Option<usize> {
+    if let Some(pos) = output.response.hover_pos() {
+        return Some(output.galley.cursor_from_pos(pos - output.galley_pos).index);
+    }
+    if output.response.has_focus() {
+        return output.cursor_range.map(|range| range.primary.index);
+    }
+    None
+}
+
+// Keep only the logical modifiers, as `egui::Modifiers::COMMAND` on
+// every platform instead of Ctrl or Cmd.
+fn normalize_modifiers(modifiers: egui::Modifiers) -> egui::Modifiers {
+    egui::Modifiers {
+        alt: modifiers.alt,
+        ctrl: modifiers.ctrl && !modifiers.command,
+        shift: modifiers.shift,
+        mac_cmd: false,
+        command: modifiers.command,
+    }
+}
+
+// Shortcuts are checked before any text is typed, so keys without a
+// modifier other than Shift would be taken from text fields, except the
+// function keys.
+fn can_bind(shortcut: egui::KeyboardShortcut) -> bool {
+    let egui::Modifiers {
+        alt, ctrl, command, ..
+    } = shortcut.modifiers;
+    alt || ctrl
+        || command
+        || shortcut
+            .logical_key
+            .name()
+            .strip_prefix('F')
+            .is_some_and(|number| number.parse::<u8>().is_ok())
+}
+
+const MODIFIER_NAMES: [&str; 4] = ["ctrl", "command", "alt", "shift"];
+// Format as `command+shift+P`, for example.
+fn format_shortcut(shortcut: egui::KeyboardShortcut) -> String {
+    let egui::Modifiers {
+        alt,
+        ctrl,
+        shift,
+        command,
+        ..
+    } = shortcut.modifiers;
+    let mut names: Vec<_> = iter::zip(MODIFIER_NAMES, [ctrl, command, alt, shift])
+        .filter_map(|(name, on)| on.then_some(name))
+        .collect();
+    names.push(shortcut.logical_key.name());
+    names.join("+")
+}
+
+fn parse_shortcut(text: &str) -> Option<egui::KeyboardShortcut> {
+    let (names, key) = text.rsplit_once('+').unwrap_or(("", text));
+    let mut modifiers = egui::Modifiers::NONE;
+    for name in names.split('+').filter(|name| !name.is_empty()) {
+        let on = match name {
+            "ctrl" => &mut modifiers.ctrl,
+            "command" => &mut modifiers.command,
+            "alt" => &mut modifiers.alt,
+            "shift" => &mut modifiers.shift,
+            _ => return None,
+        };
+        *on = true;
+    }
+    Some(egui::KeyboardShortcut::new(
+        modifiers,
+        egui::Key::from_name(key)?,
+    ))
+}
+
+fn has_han(text: &str) -> bool {
+    text.chars().any(is_han)
+}
+
+fn is_han(c: char) -> bool {
+    matches!(c,
+        '\u{3400}'..='\u{4dbf}'
+        | '\u{4e00}'..='\u{9fff}'
+        | '\u{f900}'..='\u{faff}'
+        | '\u{20000}'..='\u{3134f}')
+}
+
+fn get_file_name(path: &path::Path) -> String {
+    String::from(
+        path.file_name()
+            .expect("cannot be `None`; must have picked a regular file")
+            .to_string_lossy(),
+    )
+}
+
+// Tags used by the embedded dictionaries, following ICTCLAS.
+const TAGS: [&str; 60] = [
+    "a", "ad", "ag", "an", "b", "bg", "c", "d", "df", "dg", "e", "eng", "f", "g", "h", "i", "j",
+    "k", "l", "m", "mg", "mq", "n", "ng", "nr", "nrfg", "nrt", "ns", "nt", "nz", "o", "p", "q",
+    "qg", "r", "rg", "rr", "rz", "s", "t", "tg", "u", "ud", "ug", "uj", "ul", "uv", "uz", "v",
+    "vd", "vg", "vi", "vn", "vq", "w", "x", "y", "yg", "z", "zg",
+];
+
+// Follow `jieba::Jieba::load_dict`, except that invalid lines are skipped
+// and diagnosed instead, and that doubtful lines are diagnosed as
+// warnings.  A missing frequency or tag is taken from `defaults`.
+fn check_dict(
+    dict: &mut impl io::BufRead,
+    format: Format,
+    defaults: &Entry,
+) -> io::Result<(Words, Vec<Diagnostic>)> {
+    let mut words = Words::new();
+    let mut diagnostics = Vec::new();
+    let mut buf = Vec::new();
+    let mut line_no = 0;
+    while dict.read_until(b'\n', &mut buf)? > 0 {
+        line_no += 1;
+        let problems = match str::from_utf8(&buf) {
+            Ok(line) => check_line(&mut words, format, line, defaults)
+                .unwrap_or_else(|problem| vec![problem]),
+            Err(_) => vec![Problem::InvalidUtf8],
+        };
+        diagnostics.extend(
+            problems
+                .into_iter()
+                .map(|problem| Diagnostic { line_no, problem }),
+        );
+        buf.clear();
+    }
+    Ok((words, diagnostics))
+}
+
+// Read a table written by the build script, which is already sorted.
+fn read_table(mut table: &[u8]) -> Words {
+    let mut entries = Vec::new();
+    while !table.is_empty() {
+        let word = String::from(take_table_str(&mut table));
+        let tag = String::from(take_table_str(&mut table));
+        let freq = u64::from_le_bytes(take_table_bytes(&mut table));
+        let freq = usize::try_from(freq).expect("cannot be `Err(_)`; must have a usable frequency");
+        entries.push((word, Entry { freq, tag }));
+    }
+    Words::from_iter(entries)
+}
+
+const TABLE_EXPECT: &str = "cannot be `None`; must have read a table from the build script";
+
+fn take_table_bytes<const N: usize>(table: &mut &[u8]) -> [u8; N] {
+    let (bytes, rest) = table.split_first_chunk().expect(TABLE_EXPECT);
+    *table = rest;
+    *bytes
+}
+
+fn take_table_str<'a>(table: &mut &'a [u8]) -> &'a str {
+    let len = u32::from_le_bytes(take_table_bytes(table)) as usize;
+    let (bytes, rest) = table.split_at_checked(len).expect(TABLE_EXPECT);
+    *table = rest;
+    str::from_utf8(bytes).expect("cannot be `Err(_)`; must have written UTF-8 to the table")
+}
+
+// Return the warnings about a line that is used, or the problem of a line
+// that is skipped.  As in `jieba::Jieba::load_dict`, duplicated words
+// keep their first tag but take the last frequency.
+fn check_line(
+    words: &mut Words,
+    format: Format,
+    line: &str,
+    defaults: &Entry,
+) -> result::Result<Vec<Problem>, Problem> {
+    let Fields { word, freq, tag } = format.split_line(line);
+    let mut warnings = Vec::new();
+    let Some(word) = word else {
+        return Ok(warnings);
+    };
+    let freq = match freq {
+        Some(freq) => freq
+            .parse()
+            .map_err(|_| Problem::InvalidFreq(freq.into_owned()))?,
+        None => defaults.freq,
+    };
+    let tag = tag.map_or_else(|| defaults.tag.clone(), borrow::Cow::into_owned);
+    if !(tag.is_empty() || format.knows_tag(&tag)) {
+        warnings.push(Problem::UnknownTag(tag.clone()));
+    }
+    if let Some(entry) = words.get_mut(&*word) {
+        entry.freq = freq;
+        warnings.push(Problem::Duplicated(word.into_owned()));
+    } else {
+        words.insert(word.into_owned(), Entry { freq, tag });
+    }
+    Ok(warnings)
+}
+
+fn split_csv(line: &str) -> Vec<borrow::Cow<'_, str>> {
+    let mut fields = Vec::new();
+    let mut rest = line;
+    loop {
+        if let Some(quoted) = rest.trim_start().strip_prefix('"') {
+            let mut field = String::new();
+            rest = quoted;
+            while let Some((part, next)) = rest.split_once('"') {
+                field.push_str(part);
+                rest = next;
+                match next.strip_prefix('"') {
+                    Some(next) => {
+                        field.push('"');
+                        rest = next;
+                    }
+                    None => break,
+                }
+            }
+            fields.push(borrow::Cow::Owned(field));
+            match rest.split_once(',') {
+                Some((_, next)) => rest = next,
+                None => break,
+            }
+        } else if let Some((field, next)) = rest.split_once(',') {
+            fields.push(borrow::Cow::Borrowed(field.trim()));
+            rest = next;
+        } else {
+            fields.push(borrow::Cow::Borrowed(rest.trim()));
+            break;
+        }
+    }
+    fields
+}
+
+fn quote_csv(field: &str) -> borrow::Cow<'_, str> {
+    if field.contains([',', '"', '\n']) {
+        borrow::Cow::Owned(format!("\"{field}\"", field = field.replace('"', "\"\"")))
+    } else {
+        borrow::Cow::Borrowed(field)
+    }
+}
+
+fn quote_json(string: &str) -> String {
+    serde_json::Value::from(string).to_string()
+}
+
+fn insert_word(words: &mut Words, word: String, freq: usize, tag: String) {
+    words
+        .entry(word)
+        .and_modify(|entry| entry.freq = freq)
+        .or_insert(Entry { freq, tag });
+}
+
+fn set_entry(words: &mut Words, word: &str, entry: Option<Entry>) {
+    match entry {
+        Some(entry) => {
+            words.insert(String::from(word), entry);
+        }
+        None => {
+            words.remove(word);
+        }
+    }
+}
+
+fn with_pick_file(func: impl FnOnce(path::PathBuf) -> Result<()>) -> Result<()> {
+    match rfd::FileDialog::new().pick_file() {
+        Some(path) => func(path),
+        None => Ok(()),
+    }
+}
+
+fn with_save_file(func: impl FnOnce(path::PathBuf) -> Result<()>) -> Result<()> {
+    match rfd::FileDialog::new().save_file() {
+        Some(path) => func(path),
+        None => Ok(()),
+    }
+}
+
+// Run `func` on `in_buf` by chunks of about `len` bytes each, so that
+// large files are never held as a whole, not even a huge line.  The
+// text is trimmed as a whole, and chunks are split after line breaks if
+// any, else after whitespace or punctuation, which always separate
+// result items anyway.  `func` also gets the char offset of the chunk
+// in the whole text.
+fn for_each_chunk(
+    in_buf: &mut impl io::BufRead,
+    len: usize,
+    mut func: impl FnMut(&str, usize) -> io::Result<()>,
+) -> io::Result<()> {
+    let mut chunk = String::new();
+    // The bytes read but not moved into `chunk` yet, which are only
+    // ever the start of a char split between reads.
+    let mut bytes = Vec::new();
+    let mut started = false;
+    let mut base = 0;
+    loop {
+        let limit = len.saturating_sub(chunk.len()).max(1) as u64;
+        let done = (&mut *in_buf).take(limit).read_until(b'\n', &mut bytes)? == 0;
+        let valid_len = match str::from_utf8(&bytes) {
+            Ok(str) => str.len(),
+            Err(err) if err.error_len().is_none() && !done => err.valid_up_to(),
+            Err(err) => return Err(io::Error::new(io::ErrorKind::InvalidData, err)),
+        };
+        chunk.push_str(
+            str::from_utf8(&bytes[..valid_len])
+                .expect("cannot be `Err(_)`; must have checked the bytes before"),
+        );
+        bytes.drain(..valid_len);
+        if !done && chunk.len() < len {
+            continue;
+        }
+        if !started {
+            let blank_len = chunk.len() - chunk.trim_start().len();
+            base += chunk[..blank_len].chars().count();
+            chunk.drain(..blank_len);
+        }
+        // Split only after some text, so that every chunk makes
+        // progress, and if there is nowhere to split for a while, cut
+        // anyway, where at worst a word is split in two.
+        let blank_len = chunk.len() - chunk.trim_start().len();
+        let text = &chunk[blank_len..];
+        let cut_len = if done {
+            chunk.len()
+        } else if let Some(idx) = text.rfind('\n') {
+            blank_len + idx + 1
+        } else if let Some((idx, char)) = text
+            .char_indices()
+            .rfind(|&(_, char)| char.is_whitespace() || is_punctuation(char))
+        {
+            blank_len + idx + char.len_utf8()
+        } else if chunk.len() < len * 4 {
+            continue;
+        } else {
+            chunk.len()
+        };
+        let body_len = chunk[..cut_len].trim_end().len();
+        if body_len != 0 {
+            func(&chunk[..body_len], base)?;
+            base += chunk[..body_len].chars().count();
+            started = true;
+            chunk.drain(..body_len);
+        }
+        if done {
+            return Ok(());
+        }
+    }
+}
+
+// CJK and fullwidth punctuation, which is never part of a word, unlike
+// some ASCII punctuation such as the dots of numbers.
+fn is_punctuation(char: char) -> bool {
+    matches!(
+        char,
+        '\u{3000}'..='\u{303f}'
+            | '\u{ff01}'..='\u{ff0f}'
+            | '\u{ff1a}'..='\u{ff20}'
+            | '—'
+            | '…'
+            | '‘'
+            | '’'
+            | '“'
+            | '”'
+    )
+}
+
+#[cfg(test)]
 mod tests {
+    use super::batch::{
+        BatchProgress, COMBINE_FORMATS, FileReport, MAX_PREVIEW_LEN, manifest_path, write_chunked,
+        write_combined_file, write_out_files,
+    };
+    use super::hmm::{HMM_BEGIN, HMM_END, HMM_MIDDLE, HMM_SINGLE, MIN_LOG_PROB};
+    use super::server::{Endpoint, MAX_HEAD_LEN, Server};
+    use super::settings::{QuickSeparator, unescape};
     use super::*;
+    use std::net;
+
+    fn read_dict(strs: &[&str]) -> Words {
+        let mut str = strs.join("\n");
+        str.push('\n');
+        let (words, diagnostics) = check_dict(
+            &mut io::BufReader::new(str.as_bytes()),
+            Format::Jieba,
+            &Entry::default(),
+        )
+        .unwrap();
+        assert!(diagnostics.is_empty());
+        words
+    }
+
+    fn with_separator(mut app: App, separator: &str) -> App {
+        for settings in &mut app.settings {
+            settings.separator = String::from(separator);
+        }
+        app
+    }
+
+    fn run_output(app: &App, operation: Operation, input: &str) -> String {
+        let (output, _) = app
+            .get_layout(operation)
+            .join(&app.segmenter(operation).run_tokens(operation, input));
+        output
+    }
+
+    // A directory of the test's own, removed with its contents once
+    // dropped, even if the test fails.
+    struct TempDir(path::PathBuf);
+
+    impl TempDir {
+        fn new(name: &str) -> Self {
+            let path = env::temp_dir().join(format!("chissor-{name}-{id}", id = process::id()));
+            fs::create_dir_all(&path).unwrap();
+            TempDir(path)
+        }
+    }
+
+    impl ops::Deref for TempDir {
+        type Target = path::Path;
+
+        fn deref(&self) -> &path::Path {
+            &self.0
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn wait_batch(app: &mut App) {
+        while app.running_batch.is_some() {
+            app.poll_batch();
+            thread::sleep(time::Duration::from_millis(10));
+        }
+    }
+
+    fn wait_preview(app: &mut App) {
+        while app.previewing_batch.is_some() {
+            app.poll_batch_preview();
+            thread::sleep(time::Duration::from_millis(10));
+        }
+    }
+
+    fn cut(dicts: &Dicts) -> Vec<&str> {
+        dicts
+            .selected()
+            .cut("甲乙丙", false)
+            .into_iter()
+            .map(|jieba::Token { word, .. }| word)
+            .collect()
+    }
 
     #[test]
     fn app() {
-        let mut app = App {
-            input: String::from("分词测试案例"),
-            ..Default::default()
-        };
+        let mut app = App::default();
+        app.docs.selected_mut().input = String::from("分词测试案例");
 
-        let segment_result = vec!["分词", "测试", "案例"];
-        let segment_granular_result = vec!["分词", "测试", "案例"];
-        let search_result = vec!["分", "分词", "词", "测", "测试", "试", "案", "案例", "例"];
-        let tag_result = vec!["分词 n", "测试 vn", "案例 n"];
+        let segment_result = ["分词", "测试", "案例"];
+        let segment_granular_result = ["分词", "测试", "案例"];
+        let search_result = ["分", "分词", "词", "测", "测试", "试", "案", "案例", "例"];
+        let tag_result = ["分词 n", "测试 vn", "案例 n"];
 
-        assert_eq!(app.get_separator(), "\n");
-        app.segment();
-        assert_eq!(app.output, segment_result.join("\n"));
-        app.segment_granular();
-        assert_eq!(app.output, segment_granular_result.join("\n"));
-        app.search();
-        assert_eq!(app.output, search_result.join("\n"));
-        app.tag();
-        assert_eq!(app.output, tag_result.join("\n"));
+        assert_eq!(app.get_layout(Operation::Segment).separator, "\n");
+        app.run(Operation::Segment);
+        assert_eq!(app.docs.selected().output, segment_result.join("\n"));
+        app.run(Operation::SegmentGranular);
+        assert_eq!(
+            app.docs.selected().output,
+            segment_granular_result.join("\n")
+        );
+        app.run(Operation::Search);
+        assert_eq!(app.docs.selected().output, search_result.join("\n"));
+        app.run(Operation::Tag);
+        assert_eq!(app.docs.selected().output, tag_result.join("\n"));
 
         let separator = " / ";
-        app.separator = String::from(separator);
-        assert_eq!(app.get_separator(), separator);
-        app.segment();
-        assert_eq!(app.output, segment_result.join(separator));
-        app.segment_granular();
-        assert_eq!(app.output, segment_granular_result.join(separator));
-        app.search();
-        assert_eq!(app.output, search_result.join(separator));
-        app.tag();
-        assert_eq!(app.output, tag_result.join(separator));
+        let mut app = with_separator(app, separator);
+        assert_eq!(app.get_layout(Operation::Tag).separator, separator);
+        app.run(Operation::Segment);
+        assert_eq!(app.docs.selected().output, segment_result.join(separator));
+        app.run(Operation::SegmentGranular);
+        assert_eq!(
+            app.docs.selected().output,
+            segment_granular_result.join(separator)
+        );
+        app.run(Operation::Search);
+        assert_eq!(app.docs.selected().output, search_result.join(separator));
+        app.run(Operation::Tag);
+        assert_eq!(app.docs.selected().output, tag_result.join(separator));
 
         assert!(!app.can_add_word());
         app.word = String::from("词语");
@@ -759,34 +3905,24 @@ mod tests {
     #[test]
     fn dicts() {
         fn check_invariant(dicts: &Dicts) {
-            assert!((0..dicts.dicts.len()).contains(&dicts.idx));
-            assert!(!dicts.dicts.is_empty());
-        }
-
-        fn with_dict<T>(strs: &[&str], func: impl FnOnce(&mut io::BufReader<&[u8]>) -> T) -> T {
-            let mut str = strs.join("\n");
-            str.push('\n');
-            func(&mut io::BufReader::new(str.as_bytes()))
+            assert!((0..dicts.slots.len()).contains(&dicts.idx));
+            assert!(!dicts.slots.is_empty());
         }
 
         let mut dicts = Dicts::default();
         check_invariant(&dicts);
 
-        assert!(with_dict(&["甲", "乙 20", "丙 40 m"], |buf| {
-            dicts.new_dict("example", buf).is_ok()
-        }));
+        dicts.new_dict("example", read_dict(&["甲", "乙 20", "丙 40 m"]));
         check_invariant(&dicts);
 
-        assert!(with_dict(&["天", "地 20", "人 40 m"], |buf| {
-            dicts.load_dict(buf).is_ok()
-        }));
+        dicts.load_dict("example", read_dict(&["天", "地 20", "人 40 m"]));
 
         assert!(dicts.add_word("一", "", "").is_ok());
         assert!(dicts.add_word("二", "20", "").is_ok());
         assert!(dicts.add_word("三", "40", "m").is_ok());
         assert!(dicts.add_word("错误", "not a frequency", "").is_err());
 
-        let end = dicts.dicts.len() - 1;
+        let end = dicts.slots.len() - 1;
         dicts.idx = end;
         for _ in 0..end {
             assert!(dicts.can_remove_dict());
@@ -795,4 +3931,1182 @@ mod tests {
         }
         assert!(!dicts.can_remove_dict());
     }
+
+    #[test]
+    fn words() {
+        fn export(dicts: &Dicts) -> String {
+            let mut buf = Vec::new();
+            dicts.export_dict(&mut buf, Format::Jieba).unwrap();
+            String::from_utf8(buf).unwrap()
+        }
+
+        let mut dicts = Dicts::default();
+        dicts.new_dict(
+            "example",
+            read_dict(&["甲 10", "乙 10", "丙 10", "甲乙 40 n"]),
+        );
+        dicts.idx = dicts.slots.len() - 1;
+        assert_eq!(cut(&dicts), vec!["甲乙", "丙"]);
+
+        assert!(dicts.has_word("甲乙"));
+        dicts.disable_word("甲乙");
+        assert!(dicts.has_word("甲乙"));
+        assert!(dicts.is_disabled("甲乙"));
+        assert_eq!(cut(&dicts), vec!["甲", "乙", "丙"]);
+        assert_eq!(export(&dicts), "丙 10\n乙 10\n甲 10\n");
+
+        dicts.enable_word("甲乙");
+        assert!(!dicts.is_disabled("甲乙"));
+        assert_eq!(cut(&dicts), vec!["甲乙", "丙"]);
+        assert_eq!(export(&dicts), "丙 10\n乙 10\n甲 10\n甲乙 40 n\n");
+
+        dicts.delete_word("甲乙");
+        assert!(!dicts.has_word("甲乙"));
+        assert_eq!(cut(&dicts), vec!["甲", "乙", "丙"]);
+        assert_eq!(export(&dicts), "丙 10\n乙 10\n甲 10\n");
+    }
+
+    #[test]
+    fn layers() {
+        let mut dicts = Dicts::default();
+        dicts.new_dict("example", read_dict(&["甲 10", "乙 10", "丙 10"]));
+        dicts.idx = dicts.slots.len() - 1;
+        assert_eq!(cut(&dicts), vec!["甲", "乙", "丙"]);
+
+        dicts.load_dict("glossary", read_dict(&["乙丙 40"]));
+        assert_eq!(cut(&dicts), vec!["甲", "乙丙"]);
+        dicts.load_dict("glossary", read_dict(&["甲乙 80"]));
+        assert_eq!(cut(&dicts), vec!["甲乙", "丙"]);
+
+        dicts.toggle_layer(1);
+        assert_eq!(cut(&dicts), vec!["甲", "乙丙"]);
+        dicts.toggle_layer(1);
+        assert_eq!(cut(&dicts), vec!["甲乙", "丙"]);
+
+        dicts.disable_word("乙丙");
+        dicts.remove_layer(0);
+        assert!(!dicts.has_word("乙丙"));
+        assert!(!dicts.is_disabled("乙丙"));
+        dicts.remove_layer(0);
+        assert_eq!(cut(&dicts), vec!["甲", "乙", "丙"]);
+        dicts.undo();
+        dicts.undo();
+        assert!(dicts.is_disabled("乙丙"));
+        assert_eq!(cut(&dicts), vec!["甲乙", "丙"]);
+    }
+
+    #[test]
+    fn check() {
+        let dict = [
+            "甲\n".as_bytes(),
+            b"\xff\n",
+            "乙 x\n丙 10 zz\n丁 10 n\n丁 20\n\n戊 10 m\n".as_bytes(),
+        ]
+        .concat();
+        let (words, diagnostics) = check_dict(
+            &mut io::BufReader::new(&dict[..]),
+            Format::Jieba,
+            &Entry::default(),
+        )
+        .unwrap();
+        assert_eq!(
+            words.keys().map(String::as_str).collect::<Vec<_>>(),
+            vec!["丁", "丙", "戊", "甲"],
+        );
+        assert_eq!(words["丙"].tag, "zz");
+        assert_eq!(words["丁"].freq, 20);
+        assert_eq!(words["丁"].tag, "n");
+        let problems = diagnostics
+            .iter()
+            .map(|Diagnostic { line_no, problem }| (*line_no, problem))
+            .collect::<Vec<_>>();
+        assert!(matches!(problems[0], (2, Problem::InvalidUtf8)));
+        assert!(matches!(problems[1], (3, Problem::InvalidFreq(freq)) if freq == "x"));
+        assert!(matches!(problems[2], (4, Problem::UnknownTag(tag)) if tag == "zz"));
+        assert!(matches!(problems[3], (6, Problem::Duplicated(word)) if word == "丁"));
+        assert_eq!(problems.len(), 4);
+    }
+
+    #[test]
+    fn slots() {
+        let mut dicts = Dicts::default();
+        assert!(matches!(dicts.slots[0], Slot::Loaded(_)));
+        assert!(matches!(dicts.slots[1], Slot::Unloaded(Embedded::Small)));
+
+        dicts.select(1);
+        assert_eq!(dicts.idx, 0);
+        assert_eq!(dicts.pending, Some(1));
+        while dicts.idx != 1 {
+            thread::yield_now();
+            dicts.poll_loading();
+        }
+        assert!(matches!(dicts.slots[1], Slot::Loaded(_)));
+        assert_eq!(dicts.pending, None);
+
+        dicts.select(0);
+        assert_eq!(dicts.idx, 0);
+        assert!(matches!(dicts.slots[2], Slot::Unloaded(Embedded::Big)));
+
+        let mut dicts = Dicts::default();
+        dicts.new_dict("example", read_dict(&["甲 10"]));
+        let end = dicts.slots.len() - 1;
+        dicts.idx = 0;
+        dicts.remove_dict();
+        assert_eq!(dicts.idx, end - 1);
+        assert_eq!(dicts.pending, Some(0));
+        assert!(matches!(dicts.slots[0], Slot::Loading(Embedded::Small, _)));
+        while dicts.idx != 0 {
+            thread::yield_now();
+            dicts.poll_loading();
+        }
+
+        let text = include_bytes!("../dicts/dict.txt.small");
+        let (words, _) = check_dict(&mut &text[..], Format::Jieba, &Entry::default()).unwrap();
+        let table = Embedded::Small.words();
+        assert_eq!(table.len(), words.len());
+        for ((word, entry), (other, other_entry)) in iter::zip(&*table, &words) {
+            assert_eq!(
+                (word, entry.freq, &entry.tag),
+                (other, other_entry.freq, &other_entry.tag)
+            );
+        }
+    }
+
+    #[test]
+    fn arrange() {
+        let mut dicts = Dicts::default();
+        dicts.new_dict("example", read_dict(&["甲 10", "乙 10", "丙 10"]));
+        let end = dicts.slots.len() - 1;
+        dicts.idx = end;
+
+        dicts.rename_dict("renamed");
+        assert!(matches!(&dicts.selected_dict().name, DictName::Custom(name) if name == "renamed"));
+
+        dicts.load_dict("glossary", read_dict(&["甲乙 40"]));
+        dicts.duplicate_dict();
+        assert_eq!(dicts.slots.len(), end + 2);
+        assert_eq!(dicts.idx, end);
+        dicts.remove_layer(0);
+        assert_eq!(cut(&dicts), vec!["甲", "乙", "丙"]);
+        dicts.idx = end + 1;
+        assert_eq!(cut(&dicts), vec!["甲乙", "丙"]);
+
+        assert!(!dicts.can_move_down());
+        dicts.move_up();
+        assert_eq!(dicts.idx, end);
+        assert_eq!(cut(&dicts), vec!["甲乙", "丙"]);
+        dicts.move_down();
+        assert_eq!(dicts.idx, end + 1);
+
+        dicts.idx = 0;
+        assert!(!dicts.can_move_up());
+        dicts.pending = Some(1);
+        dicts.move_down();
+        assert_eq!(dicts.idx, 1);
+        assert_eq!(dicts.pending, Some(0));
+    }
+
+    #[test]
+    fn formats() {
+        let dict = [
+            (Format::Jieba, "甲 10 n\n乙\n"),
+            (Format::Csv, "甲,10,n\n\"乙\",,\n"),
+            (Format::Tsv, "甲\t10\tn\n乙\n"),
+            (Format::HanLp, "甲 n 10\n乙\n"),
+        ];
+        let defaults = Entry {
+            freq: 5,
+            tag: String::from("m"),
+        };
+        for (format, dict) in dict {
+            let (words, diagnostics) =
+                check_dict(&mut io::BufReader::new(dict.as_bytes()), format, &defaults).unwrap();
+            assert!(diagnostics.is_empty());
+            assert_eq!((words["甲"].freq, &*words["甲"].tag), (10, "n"));
+            assert_eq!((words["乙"].freq, &*words["乙"].tag), (5, "m"));
+        }
+
+        let dict = [
+            (Format::Plain, "甲\n 乙 \n"),
+            (Format::Pkuseg, "甲\n乙\tn\n"),
+        ];
+        for (format, dict) in dict {
+            let (words, diagnostics) =
+                check_dict(&mut io::BufReader::new(dict.as_bytes()), format, &defaults).unwrap();
+            assert!(diagnostics.is_empty());
+            assert_eq!(words["甲"].freq, 5);
+            assert_eq!(words["乙"].freq, 5);
+        }
+
+        let dict = [
+            (Format::Jieba, "甲 10 NN\n", 1),
+            (Format::HanLp, "甲 NN 10\n", 0),
+            (Format::Pkuseg, "甲\tnx\n", 0),
+        ];
+        for (format, dict, len) in dict {
+            let (_, diagnostics) =
+                check_dict(&mut io::BufReader::new(dict.as_bytes()), format, &defaults).unwrap();
+            assert_eq!(diagnostics.len(), len);
+        }
+
+        let mut dicts = Dicts::default();
+        dicts.new_dict("example", read_dict(&["甲 10 n", "乙,丙 20"]));
+        dicts.idx = dicts.slots.len() - 1;
+        let export = |format| {
+            let mut buf = Vec::new();
+            dicts.export_dict(&mut buf, format).unwrap();
+            String::from_utf8(buf).unwrap()
+        };
+        assert_eq!(export(Format::Csv), "\"乙,丙\",20,\n甲,10,n\n");
+        assert_eq!(export(Format::Tsv), "乙,丙\t20\t\n甲\t10\tn\n");
+        assert_eq!(export(Format::Plain), "乙,丙\n甲\n");
+        assert_eq!(export(Format::HanLp), "乙,丙 nz 20\n甲 n 10\n");
+        assert_eq!(export(Format::Pkuseg), "乙,丙\n甲\tn\n");
+        for format in FORMATS {
+            let (words, diagnostics) = check_dict(
+                &mut io::BufReader::new(export(format).as_bytes()),
+                format,
+                &defaults,
+            )
+            .unwrap();
+            assert!(diagnostics.is_empty());
+            assert!(words.contains_key("乙,丙"));
+        }
+    }
+
+    #[test]
+    fn docs() {
+        let mut app = App::default();
+        app.docs.selected_mut().input = String::from("甲乙丙");
+        app.dicts.new_dict(
+            "example",
+            read_dict(&["甲 10", "乙 10", "丙 10", "甲乙 40"]),
+        );
+        app.docs.new_doc(app.dicts.selected_id());
+        assert_eq!(app.docs.idx, 1);
+        app.docs.selected_mut().input = String::from("甲乙丙");
+        app.dicts.select(app.dicts.slots.len() - 1);
+        app.docs.selected_mut().dict = app.dicts.selected_id();
+        app.run(Operation::Search);
+        assert_eq!(app.docs.selected().output, "甲\n甲乙\n乙\n丙");
+        assert!(app.docs.selected().operation == Operation::Search);
+
+        app.select_doc(0);
+        assert_eq!(app.dicts.idx, 0);
+        assert!(app.docs.selected().output.is_empty());
+        app.select_doc(1);
+        assert_eq!(app.dicts.idx, app.dicts.slots.len() - 1);
+
+        app.dicts.remove_dict();
+        app.select_doc(0);
+        app.select_doc(1);
+        assert_eq!(app.dicts.idx, 0);
+
+        assert!(app.docs.can_close_doc());
+        app.docs.close_doc();
+        assert_eq!(app.docs.idx, 0);
+        assert!(!app.docs.can_close_doc());
+    }
+
+    #[test]
+    fn project() {
+        let mut app = App {
+            preview_lines: 5,
+            ..Default::default()
+        };
+        *app.settings_mut(Operation::Tag) = Settings {
+            template: String::from("{word}/{tag}"),
+            separator: String::from("\t|\\n"),
+            line_separator: String::from("\n\n"),
+            use_hmm: true,
+        };
+        app.settings_operation = Operation::Tag;
+        app.preset_name = String::from("slash\ttag");
+        app.save_preset();
+        app.dicts
+            .new_dict("示例\t1", read_dict(&["甲 10", "乙 10", "丙 10"]));
+        app.dicts.select(app.dicts.slots.len() - 1);
+        app.docs.selected_mut().dict = app.dicts.selected_id();
+        app.dicts.load_dict("layer", read_dict(&["甲乙 40"]));
+        app.dicts.add_word("乙丙", "50", "n").unwrap();
+        app.dicts.add_word("丁", "", "").unwrap();
+        app.dicts.disable_word("丙");
+        app.docs.selected_mut().input = String::from("甲乙丙\n丁");
+        app.run(Operation::Tag);
+        app.docs.new_doc(0);
+        app.docs.selected_mut().name = Some(String::from("doc.txt"));
+        app.docs.selected_mut().input = String::from("甲乙丙");
+        app.select_doc(0);
+
+        let mut buf = Vec::new();
+        app.write_project(&mut buf).unwrap();
+        let mut other = App::default();
+        other.read_project(&mut &*buf).unwrap();
+        assert!(other.settings == app.settings);
+        assert_eq!(other.presets[0].name, "slash\ttag");
+        assert!(other.presets[0].settings == *app.settings(Operation::Tag));
+        assert_eq!(other.preview_lines, 5);
+        assert_eq!(other.dicts.idx, app.dicts.idx);
+        assert_eq!(other.dicts.ids, app.dicts.ids);
+        assert_eq!(other.dicts.next_id, app.dicts.next_id);
+        assert_eq!(cut(&other.dicts), cut(&app.dicts));
+        assert!(matches!(
+            other.dicts.slots[1],
+            Slot::Unloaded(Embedded::Small)
+        ));
+        assert_eq!(other.docs.idx, 0);
+        assert_eq!(other.docs.selected().output, app.docs.selected().output);
+        assert!(other.docs.selected().operation == Operation::Tag);
+        assert!(other.docs.selected().items.is_empty());
+        assert!(!CopyFormat::Lines.can_render(other.docs.selected()));
+        assert_eq!(other.docs.docs[1].name.as_deref(), Some("doc.txt"));
+        let mut other_buf = Vec::new();
+        other.write_project(&mut other_buf).unwrap();
+        assert_eq!(other_buf, buf);
+
+        let mut app = App::default();
+        assert!(
+            app.read_project(&mut "chissor-project\t3\n".as_bytes())
+                .is_err()
+        );
+        for record in [&b"selected-dict\t9\n"[..], b"workers\t4\n"] {
+            let invalid = [&buf[..], record].concat();
+            assert!(app.read_project(&mut &*invalid).is_err());
+        }
+        assert!(
+            app.settings
+                .iter()
+                .all(|settings| settings.separator.is_empty())
+        );
+    }
+
+    #[test]
+    fn drop() {
+        let dir = TempDir::new("drop");
+        let paths = [
+            dir.join("chissor-drop-1.txt"),
+            dir.join("chissor-drop-2.txt"),
+        ];
+        fs::write(&paths[0], "甲 10\n乙 x\n").unwrap();
+        fs::write(&paths[1], "甲乙 10\n").unwrap();
+        let mut app = App::default();
+        let len = app.dicts.slots.len();
+        app.queue_checks(paths.to_vec(), CheckKind::New);
+        assert!(app.check_dialog.is_some());
+        assert_eq!(app.checks.len(), 1);
+        assert_eq!(app.dicts.slots.len(), len);
+        app.close_check();
+        app.next_check();
+        assert!(app.check_dialog.is_none());
+        assert!(app.checks.is_empty());
+        assert_eq!(app.dicts.slots.len(), len + 2);
+
+        app.queue_checks(vec![paths[1].clone()], CheckKind::Load);
+        assert_eq!(app.dicts.selected_dict().layers.len(), 1);
+        app.queue_checks(vec![dir.join("chissor-drop-0.txt")], CheckKind::Load);
+        assert!(app.error_dialog.is_some());
+
+        app.import_file(&paths[1]).unwrap();
+        assert_eq!(app.docs.selected().input, "甲乙 10");
+        assert_eq!(
+            app.docs.selected().name.as_deref(),
+            Some("chissor-drop-2.txt")
+        );
+    }
+
+    #[test]
+    fn history() {
+        let mut dicts = Dicts::default();
+        dicts.new_dict("example", read_dict(&["甲 10", "乙 10", "丙 10"]));
+        let end = dicts.slots.len() - 1;
+        assert!(dicts.can_undo());
+        dicts.undo();
+        assert_eq!(dicts.slots.len(), end);
+        assert!(!dicts.can_undo());
+        dicts.redo();
+        assert_eq!(dicts.slots.len(), end + 1);
+        assert!(!dicts.can_redo());
+
+        dicts.select(end);
+        dicts.add_word("甲乙", "40", "").unwrap();
+        dicts.load_dict("glossary", read_dict(&["乙丙 80"]));
+        assert_eq!(cut(&dicts), vec!["甲", "乙丙"]);
+        dicts.undo();
+        assert_eq!(cut(&dicts), vec!["甲乙", "丙"]);
+        dicts.undo();
+        assert_eq!(cut(&dicts), vec!["甲", "乙", "丙"]);
+        dicts.redo();
+        assert_eq!(cut(&dicts), vec!["甲乙", "丙"]);
+        assert!(dicts.can_redo());
+        dicts.disable_word("甲乙");
+        assert!(!dicts.can_redo());
+        assert_eq!(cut(&dicts), vec!["甲", "乙", "丙"]);
+
+        dicts.new_dict("other", read_dict(&["甲 10"]));
+        let id = dicts.selected_id();
+        dicts.move_down();
+        dicts.remove_dict();
+        assert!(!dicts.ids.contains(&id));
+        assert_eq!(dicts.idx, end);
+        dicts.undo();
+        assert_eq!(dicts.ids[end + 1], id);
+        dicts.undo();
+        assert_eq!(dicts.ids[end], id);
+        assert_eq!(dicts.idx, end + 1);
+        dicts.undo();
+        assert_eq!(dicts.slots.len(), end + 1);
+        dicts.idx = 0;
+        dicts.undo();
+        assert_eq!(dicts.idx, end);
+        assert_eq!(cut(&dicts), vec!["甲乙", "丙"]);
+        dicts.undo();
+        assert_eq!(cut(&dicts), vec!["甲", "乙", "丙"]);
+        dicts.idx = 0;
+        dicts.undo();
+        assert_eq!(dicts.slots.len(), end);
+        assert_eq!(dicts.future.len(), 6);
+
+        dicts.disable_word("的");
+        dicts.disable_word("的");
+        assert_eq!(dicts.history.len(), 1);
+        for _ in 0..HISTORY_LIMIT / 2 {
+            dicts.enable_word("的");
+            dicts.disable_word("的");
+        }
+        assert_eq!(dicts.history.len(), HISTORY_LIMIT);
+        assert_eq!(dicts.history[0].key, "history.enable-word");
+        assert!(dicts.future.is_empty());
+    }
+
+    #[test]
+    fn json() {
+        let server = Server {
+            dict: Dict::new(DictName::Custom(String::from("example")), Words::new()),
+            use_hmm: [false; OPERATIONS.len()],
+        };
+        let body = r#" {"text": "甲\n\u4e59\ud83d\ude00", "top_k": 3, "allowed_pos": ["n"]} "#;
+        let request = server
+            .parse_request(Endpoint::Cut, body.as_bytes())
+            .unwrap();
+        assert_eq!(request.text, "甲\n乙😀");
+        assert_eq!(request.top_k, 3);
+        assert_eq!(request.allowed_pos, ["n"]);
+        assert!(!request.use_hmm);
+        for invalid in [
+            "",
+            "{",
+            r#"{"text" "甲"}"#,
+            r#"["甲"]"#,
+            r#"{"text": 1}"#,
+            r#"{"text": "甲", "hmm": null}"#,
+            r#"{"text": "甲", "top_k": -1}"#,
+            r#"{"text": "甲", "allowed_pos": [1]}"#,
+        ] {
+            assert!(
+                server
+                    .parse_request(Endpoint::Cut, invalid.as_bytes())
+                    .is_none()
+            );
+        }
+        let mut server = server;
+        server.use_hmm[Operation::Tag as usize] = true;
+        let body = r#"{"text": "甲"}"#.as_bytes();
+        assert!(server.parse_request(Endpoint::Tag, body).unwrap().use_hmm);
+        assert!(!server.parse_request(Endpoint::Cut, body).unwrap().use_hmm);
+        let body = r#"{"text": "甲", "hmm": false}"#.as_bytes();
+        assert!(server.parse_request(Endpoint::CutAll, body).is_none());
+        assert!(server.parse_request(Endpoint::Keywords, body).is_none());
+        assert_eq!(quote_json("\"甲\"\\\n\u{1}"), r#""\"甲\"\\\n\u0001""#);
+    }
+
+    #[test]
+    fn serve() {
+        let server = Server {
+            dict: Dict::new(
+                DictName::Custom(String::from("example")),
+                read_dict(&["甲 10 n", "乙 10 n", "丙 10 n", "甲乙 40 n"]),
+            ),
+            use_hmm: [false; OPERATIONS.len()],
+        };
+        let listener = net::TcpListener::bind((net::Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let addr = listener.local_addr().unwrap();
+        let request = |request: &str| {
+            let mut stream = net::TcpStream::connect(addr).unwrap();
+            stream.write_all(request.as_bytes()).unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).unwrap();
+            response
+        };
+        let post = |path: &str, body: &str| {
+            request(&format!(
+                "POST {path} HTTP/1.1\r\nHost: localhost\r\nContent-Length: {len}\r\n\r\n{body}",
+                len = body.len(),
+            ))
+        };
+        let parse_body = |response: &str| -> serde_json::Value {
+            let (_, body) = response.split_once("\r\n\r\n").unwrap();
+            serde_json::from_str(body).unwrap()
+        };
+        thread::scope(|scope| {
+            scope.spawn(|| {
+                for stream in listener.incoming().take(8) {
+                    server.handle(&stream.unwrap()).unwrap();
+                }
+            });
+            let response = post("/cut", r#"{"text":"甲乙丙"}"#);
+            assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+            assert_eq!(
+                parse_body(&response),
+                serde_json::json!({"tokens": [
+                    {"word": "甲乙", "start": 0, "end": 2},
+                    {"word": "丙", "start": 2, "end": 3},
+                ]})
+            );
+            let response = post("/tag", r#"{"text":"甲乙丙","hmm":true}"#);
+            assert_eq!(
+                parse_body(&response),
+                serde_json::json!({"tags": [
+                    {"word": "甲乙", "tag": "n"},
+                    {"word": "丙", "tag": "n"},
+                ]})
+            );
+            let response = post("/keywords", r#"{"text":"甲乙丙","top_k":1}"#);
+            assert_eq!(
+                parse_body(&response)["keywords"].as_array().unwrap().len(),
+                1
+            );
+            assert!(post("/keywords", r#"{"text":"甲","hmm":true}"#).starts_with("HTTP/1.1 400 "));
+            assert!(post("/cut", r#"{"hmm":true}"#).starts_with("HTTP/1.1 400 "));
+            assert!(post("/split", r#"{"text":"甲"}"#).starts_with("HTTP/1.1 404 "));
+            assert!(request("GET /cut HTTP/1.1\r\n\r\n").starts_with("HTTP/1.1 405 "));
+            let request_line = "POST /cut HTTP/1.1\r\nX-Padding: ";
+            let padding = "a".repeat(MAX_HEAD_LEN - request_line.len());
+            let response = request(&format!("{request_line}{padding}"));
+            assert!(response.starts_with("HTTP/1.1 431 "));
+        });
+    }
+
+    #[test]
+    fn copy() {
+        let mut app = with_separator(App::default(), " / ");
+        app.run_text(String::from("分词测试案例"));
+        let doc = app.docs.selected();
+        assert_eq!(CopyFormat::Text.render(doc), "分词 / 测试 / 案例");
+        assert_eq!(CopyFormat::Lines.render(doc), "分词\n测试\n案例");
+        assert_eq!(CopyFormat::Json.render(doc), r#"["分词","测试","案例"]"#);
+        app.run(Operation::Tag);
+        let items: Vec<_> = app
+            .docs
+            .selected()
+            .items
+            .iter()
+            .map(|item| &*item.text)
+            .collect();
+        assert_eq!(items, vec!["分词 n", "测试 vn", "案例 n"]);
+
+        assert!(has_han("copy 中文"));
+        assert!(has_han("𠀀"));
+        assert!(!has_han("copy, 。"));
+        assert!(app.clipboard.poll(&egui::Context::default()).is_none());
+    }
+
+    #[test]
+    fn items() {
+        let mut app = with_separator(App::default(), " / ");
+        app.docs.selected_mut().input = String::from("分词测试案例");
+        let ranges = |app: &App| -> Vec<_> {
+            app.docs
+                .selected()
+                .items
+                .iter()
+                .map(|item| (item.input.clone(), item.output.clone()))
+                .collect()
+        };
+        app.run(Operation::Segment);
+        assert_eq!(
+            ranges(&app),
+            vec![(0..2, 0..2), (2..4, 5..7), (4..6, 10..12)]
+        );
+        app.run(Operation::Tag);
+        assert_eq!(
+            ranges(&app),
+            vec![(0..2, 0..4), (2..4, 7..12), (4..6, 15..19)]
+        );
+
+        app.run(Operation::Segment);
+        app.focus_item(Some(3), Some(6));
+        assert_eq!(app.focus, Some(1));
+        assert_eq!(app.highlight(|item| item.input.clone()), Some(2..4));
+        app.focus_item(Some(5), Some(3));
+        assert_eq!(app.focus, Some(2));
+        app.focus_item(None, None);
+        assert_eq!(app.focus, None);
+        app.focus_item(Some(0), None);
+        app.docs.selected_mut().edited = true;
+        assert_eq!(app.highlight(|item| item.output.clone()), None);
+
+        assert_eq!(get_byte_idx("甲a乙", 2), 4);
+        assert_eq!(get_byte_idx("甲a乙", 3), 7);
+    }
+
+    #[test]
+    fn settings() {
+        let mut app = App::default();
+        app.docs.selected_mut().input = String::from("分词测试\n\n案例");
+        *app.settings_mut(Operation::Tag) = Settings {
+            template: String::from("{word}/{tag}{{tag}"),
+            separator: String::from(" "),
+            line_separator: String::from(" | "),
+            use_hmm: false,
+        };
+        app.run(Operation::Tag);
+        let doc = app.docs.selected();
+        assert_eq!(doc.output, "分词/n{n 测试/vn{vn |  | 案例/n{n");
+        assert_eq!(doc.items.len(), 3);
+        assert_eq!(doc.items[2].input, 6..8);
+        assert_eq!(doc.items[2].output, 21..27);
+        app.run(Operation::Segment);
+        assert_eq!(app.docs.selected().output, "分词\n测试\n\n案例");
+
+        assert!(!app.can_save_preset());
+        app.settings_operation = Operation::Tag;
+        app.preset_name = String::from("slash");
+        app.save_preset();
+        app.settings_mut(Operation::Tag).separator = String::from(", ");
+        app.save_preset();
+        assert_eq!(app.presets.len(), 1);
+        app.settings_operation = Operation::Segment;
+        app.apply_preset(0);
+        app.run(Operation::Segment);
+        assert_eq!(app.docs.selected().output, "分词/{, 测试/{ |  | 案例/{");
+
+        app.settings_mut(Operation::Search).template =
+            String::from("{index}\\t{word}\\t{start}-{end}\\{end}");
+        app.run(Operation::Search);
+        let output = app.docs.selected().output.clone();
+        assert_eq!(output.lines().nth(1), Some("1\t分词\t0-2\\2"));
+        assert_eq!(output.lines().next_back(), Some("8\t例\t7-8\\8"));
+        let input = "  分词\n\n测试案例\n";
+        let mut buf = Vec::new();
+        let layout = app.get_layout(Operation::Search);
+        write_chunked(&mut input.as_bytes(), &mut buf, &layout, 16, |chunk| {
+            app.segmenter(Operation::Search)
+                .run_tokens(Operation::Search, chunk)
+        })
+        .unwrap();
+        let tokens: Vec<_> = app
+            .segmenter(Operation::Search)
+            .run_tokens(Operation::Search, input.trim())
+            .into_iter()
+            .map(|token| token.shift(2))
+            .collect();
+        let (expected, _) = layout.join(&tokens);
+        assert!(expected.contains("\n4\t测试\t6-8\\8\n"));
+        assert_eq!(String::from_utf8(buf).unwrap(), expected + "\n");
+        assert_eq!(unescape("a\\\\t\\t\\x\\"), "a\\t\t\\x\\");
+        assert_eq!(
+            unescape("\\u{3000}\\u{}\\u{d800}\\u3000"),
+            "\u{3000}\\u{}\\u{d800}\\u3000"
+        );
+
+        *app.settings_mut(Operation::Segment) = Settings {
+            separator: String::from(QuickSeparator::IdeographicSpace.to_setting()),
+            line_separator: String::from(QuickSeparator::Tab.to_setting()),
+            ..Default::default()
+        };
+        app.run(Operation::Segment);
+        assert_eq!(app.docs.selected().output, "分词\u{3000}测试\t\t案例");
+        assert_eq!(app.docs.selected().items[1].output, 3..5);
+
+        assert_eq!(count_line_breaks("\r\n\n"), Some(2));
+        assert_eq!(count_line_breaks(" \n"), None);
+        assert_eq!(count_line_breaks(""), None);
+    }
+
+    #[test]
+    fn shortcuts() {
+        let shortcut = |modifiers, key| egui::KeyboardShortcut::new(modifiers, key);
+        let mut app = App::default();
+        let save = shortcut(COMMAND_SHIFT, egui::Key::S);
+        assert!(app.shortcuts.get(Command::SaveProject) == Some(save));
+        assert!(app.shortcuts.bindings[0].1.modifiers == COMMAND_SHIFT);
+        app.shortcuts.set(Command::Undo, Some(save));
+        assert!(app.shortcuts.get(Command::SaveProject).is_none());
+        assert!(app.shortcuts.get(Command::Undo) == Some(save));
+        app.shortcuts.set(Command::Export, None);
+        assert!(app.shortcuts.get(Command::Export).is_none());
+        let undo = shortcut(egui::Modifiers::COMMAND, egui::Key::Z);
+        app.shortcuts.set(Command::Undo, Some(undo));
+        assert!(app.shortcuts.get(Command::Undo) == Some(undo));
+        assert_eq!(app.shortcuts.bindings.len(), DEFAULT_SHORTCUTS.len() - 1);
+
+        assert_eq!(format_shortcut(save), "command+shift+S");
+        assert!(parse_shortcut("command+shift+S") == Some(save));
+        assert!(parse_shortcut("F2") == Some(shortcut(egui::Modifiers::NONE, egui::Key::F2)));
+        assert!(parse_shortcut("hyper+S").is_none());
+        assert!(parse_shortcut("command+").is_none());
+        assert!(can_bind(save) && can_bind(parse_shortcut("F2").unwrap()));
+        assert!(!can_bind(parse_shortcut("shift+S").unwrap()));
+        assert!(!can_bind(parse_shortcut("Space").unwrap()));
+        let mac_cmd = egui::Modifiers {
+            mac_cmd: true,
+            ..egui::Modifiers::COMMAND
+        };
+        assert!(normalize_modifiers(mac_cmd) == egui::Modifiers::COMMAND);
+        assert!(normalize_modifiers(egui::Modifiers::CTRL) == egui::Modifiers::CTRL);
+
+        app.workers = 3;
+        let mut other = App::default();
+        other.read_preferences(&app.write_preferences());
+        assert_eq!(other.workers, 3);
+        assert!(other.shortcuts.get(Command::Undo) == Some(undo));
+        assert!(other.shortcuts.get(Command::Export).is_none());
+        assert!(other.shortcuts.get(Command::SaveProject).is_none());
+
+        for command in COMMANDS {
+            assert!(Command::from_key(command.to_key()) == Some(command));
+        }
+        assert!(Command::Run(Operation::Segment).matches(""));
+        assert!(Command::NewDict.matches("NEW dict"));
+        assert!(!Command::NewDict.matches("new doc"));
+
+        assert!(!app.can_run_command(Command::CloseDoc));
+        app.run_command(Command::NewDoc);
+        assert_eq!(app.docs.docs.len(), 2);
+        app.run_command(Command::CloseDoc);
+        assert_eq!(app.docs.docs.len(), 1);
+        app.run_command(Command::BatchMode);
+        assert!(!app.can_run_command(Command::Export));
+        app.run_command(Command::Palette);
+        assert!(app.palette.is_some());
+    }
+
+    #[test]
+    fn large() {
+        let app = with_separator(App::default(), " / ");
+        let layout = app.get_layout(Operation::Segment);
+        let input = "  分词测试\n\n案例 \r\n测试\n  ";
+        let mut chunks = Vec::new();
+        let mut buf = Vec::new();
+        write_chunked(&mut input.as_bytes(), &mut buf, &layout, 8, |chunk| {
+            chunks.push(String::from(chunk));
+            app.segmenter(Operation::Segment)
+                .run_tokens(Operation::Segment, chunk)
+        })
+        .unwrap();
+        assert_eq!(chunks, vec!["分词测试", "\n\n案例", " \r\n测试"]);
+        let output = run_output(&app, Operation::Segment, input.trim());
+        assert_eq!(output, "分词 / 测试\n\n案例 /  \n测试");
+        assert_eq!(String::from_utf8(buf).unwrap(), output + "\n");
+        let mut buf = Vec::new();
+        write_chunked(
+            &mut " \n ".as_bytes(),
+            &mut buf,
+            &layout,
+            1,
+            |_| unreachable!(),
+        )
+        .unwrap();
+        assert_eq!(buf, b"\n");
+        let chunks = |input: &str, len| {
+            let mut chunks = Vec::new();
+            for_each_chunk(&mut input.as_bytes(), len, |chunk, base| {
+                chunks.push((String::from(chunk), base));
+                Ok(())
+            })
+            .unwrap();
+            chunks
+        };
+        assert_eq!(
+            chunks("分词测试。分词 测试测试", 8),
+            vec![
+                (String::from("分词测试。"), 0),
+                (String::from("分词"), 5),
+                (String::from(" 测试测试"), 7),
+            ]
+        );
+        assert_eq!(
+            chunks("测试测试测试测试", 4),
+            vec![(String::from("测试测试测试"), 0), (String::from("测试"), 6)]
+        );
+        assert!(for_each_chunk(&mut &b"\xe6\xb5"[..], 4, |_, _| Ok(())).is_err());
+
+        let mut app = app;
+        let input = "分词测试\n".repeat(LARGE_TEXT_LEN / 10);
+        app.docs.selected_mut().input = input.clone();
+        app.run(Operation::Segment);
+        while app.docs.selected().running.is_some() {
+            app.docs.poll_running();
+            thread::sleep(time::Duration::from_millis(10));
+        }
+        assert_eq!(
+            app.docs.selected().output,
+            run_output(&app, Operation::Segment, &input)
+        );
+
+        assert_eq!(split_rows("", 2), vec![0..0]);
+        assert_eq!(
+            split_rows("甲乙丙\n\nab", 2),
+            vec![0..6, 6..9, 10..10, 11..13]
+        );
+    }
+
+    #[test]
+    fn workers() {
+        let app = with_separator(App::default(), " / ");
+        let layout = app.get_layout(Operation::Segment);
+        let dir = TempDir::new("workers");
+        let (in_dir, out_dir) = (dir.join("in"), dir.join("out"));
+        fs::create_dir_all(&in_dir).unwrap();
+        fs::create_dir_all(&out_dir).unwrap();
+        let inputs = ["分词测试", "测试案例\n分词", " 案例 ", "分词", "测试"];
+        let in_paths: Vec<_> = inputs
+            .iter()
+            .enumerate()
+            .map(|(idx, input)| {
+                let in_path = in_dir.join(format!("{idx}.txt"));
+                fs::write(&in_path, input).unwrap();
+                in_path
+            })
+            .collect();
+        let segmenter = app.segmenter(Operation::Segment);
+        let func = |input: &str| segmenter.run_tokens(Operation::Segment, input);
+        let report = write_out_files(&in_paths, &out_dir, &layout, 3, false, None, func);
+        assert!(report.first_error().is_none());
+        assert_eq!(report.files.len(), inputs.len());
+        for (file, input) in iter::zip(&report.files, inputs) {
+            assert_eq!(file.size, input.len() as u64);
+        }
+        for (in_path, input) in iter::zip(&in_paths, inputs) {
+            let output = fs::read_to_string(out_dir.join(in_path.file_name().unwrap())).unwrap();
+            let expected = run_output(&app, Operation::Segment, input.trim());
+            assert_eq!(output, expected + "\n");
+        }
+        let report = write_out_files(&in_paths, &out_dir, &layout, 2, false, None, func);
+        assert!(report.files.iter().all(|file| file.error.is_some()));
+        let report = write_out_files(&in_paths, &out_dir, &layout, 2, true, None, func);
+        assert!(report.first_error().is_none());
+        let (events, receiver) = mpsc::channel();
+        let progress = BatchProgress {
+            events,
+            cancel: sync::Arc::new(sync::atomic::AtomicBool::new(true)),
+        };
+        let report = write_out_files(&in_paths, &out_dir, &layout, 2, true, Some(&progress), func);
+        assert!(report.files.iter().all(|file| file.error.is_some()));
+        assert_eq!(receiver.try_iter().count(), inputs.len());
+
+        let workers = App {
+            workers: 3,
+            ..Default::default()
+        };
+        assert_eq!(workers.get_workers(), 3);
+        assert!(app.get_workers() >= 1);
+    }
+
+    #[test]
+    fn combine() {
+        let tokens = ["分词", "\t", "\n", "案例"].map(|word| Token {
+            word: String::from(word),
+            tag: String::new(),
+            input: 0..0,
+        });
+        let settings = Settings {
+            separator: String::from(" / "),
+            ..Default::default()
+        };
+        let layout = settings.to_layout(Operation::Segment);
+        assert_eq!(
+            CombineFormat::JsonLines.render("a.txt", &tokens, &layout),
+            "{\"file\":\"a.txt\",\"items\":[\"分词\",\"\\t\",\"案例\"]}\n",
+        );
+        assert_eq!(
+            CombineFormat::Tsv.render("a\tb.txt", &tokens[..2], &layout),
+            "a\\tb.txt\t分词\na\\tb.txt\t\\t\n",
+        );
+        assert_eq!(
+            CombineFormat::Text.render("a.txt", &tokens, &layout),
+            "==> a.txt <==\n分词 / \t\n案例\n\n",
+        );
+        for format in COMBINE_FORMATS {
+            assert!(CombineFormat::from_key(format.to_key()) == Some(format));
+        }
+
+        let app = App::default();
+        let dir = TempDir::new("combine");
+        let inputs = ["分词测试", "测试案例", "案例"];
+        let in_paths: Vec<_> = inputs
+            .iter()
+            .enumerate()
+            .map(|(idx, input)| {
+                let in_path = dir.join(format!("{idx}.txt"));
+                fs::write(&in_path, input).unwrap();
+                in_path
+            })
+            .collect();
+        let out_path = dir.join("out.jsonl");
+        let segmenter = app.segmenter(Operation::Segment);
+        let func = |input: &str| segmenter.run_tokens(Operation::Segment, input);
+        let format = CombineFormat::JsonLines;
+        let report =
+            write_combined_file(&in_paths, &out_path, format, &layout, 2, None, func).unwrap();
+        assert!(report.first_error().is_none());
+        let expected: String = iter::zip(&in_paths, inputs)
+            .map(|(in_path, input)| format.render(&get_file_name(in_path), &func(input), &layout))
+            .collect();
+        assert_eq!(fs::read_to_string(&out_path).unwrap(), expected);
+        fs::remove_file(&in_paths[1]).unwrap();
+        let report =
+            write_combined_file(&in_paths, &out_path, format, &layout, 2, None, func).unwrap();
+        let failed: Vec<_> = report
+            .files
+            .iter()
+            .map(|file| file.error.is_some())
+            .collect();
+        assert_eq!(failed, [false, true, false]);
+        let expected: String = [0, 2]
+            .map(|idx| format.render(&get_file_name(&in_paths[idx]), &func(inputs[idx]), &layout))
+            .concat();
+        assert_eq!(fs::read_to_string(&out_path).unwrap(), expected);
+        assert!(!dir.join("out.jsonl.part").exists());
+    }
+
+    #[test]
+    fn manifest() {
+        let mut app = App::default();
+        let dir = TempDir::new("manifest");
+        let (in_dir, out_dir) = (dir.join("in"), dir.join("out"));
+        fs::create_dir_all(&in_dir).unwrap();
+        fs::create_dir_all(&out_dir).unwrap();
+        let in_paths: Vec<_> = ["分词测试", "测试案例", "案例"]
+            .iter()
+            .enumerate()
+            .map(|(idx, input)| {
+                let in_path = in_dir.join(format!("{idx}.txt"));
+                fs::write(&in_path, input).unwrap();
+                in_path
+            })
+            .collect();
+        fs::write(out_dir.join("1.txt"), "").unwrap();
+        let dict = app.dicts.selected_dict();
+        let manifest = Manifest {
+            settings: Settings {
+                separator: String::from("\t"),
+                ..Default::default()
+            },
+            dict: dict.name.to_name(),
+            fingerprint: dict.fingerprint(),
+            output: out_dir.clone(),
+            ..Default::default()
+        };
+        let runner = app.runner(Operation::Segment);
+        app.run_manifest(
+            manifest,
+            runner,
+            in_paths.clone(),
+            false,
+            "rerun-batch.what",
+        );
+        // A dialog already open keeps the batch error until it is closed.
+        app.open_error("copy.what", "".into());
+        wait_batch(&mut app);
+        assert_eq!(app.error_dialog.as_ref().unwrap().what, "copy.what");
+        app.close_error();
+        assert_eq!(app.error_dialog.take().unwrap().what, "rerun-batch.what");
+        let manifest = app.last_batch.take().unwrap();
+        assert!(manifest.report.files[1].error.is_some());
+
+        let mut buf = Vec::new();
+        manifest.write(&mut buf).unwrap();
+        let read = Manifest::read(&mut buf.as_slice()).unwrap();
+        let mut read_buf = Vec::new();
+        read.write(&mut read_buf).unwrap();
+        assert_eq!(buf, read_buf);
+        assert!(Manifest::read(&mut &buf[1..]).is_err());
+        assert_eq!(fs::read(manifest.to_path()).unwrap(), buf);
+        let needs_rerun = |manifest: &Manifest| -> Vec<bool> {
+            let files = &manifest.report.files;
+            files.iter().map(FileReport::needs_rerun).collect()
+        };
+        assert_eq!(needs_rerun(&read), [false, true, false]);
+        #[cfg(unix)]
+        {
+            use std::{ffi, os::unix::ffi::OsStrExt as _};
+            let path = path::Path::new(ffi::OsStr::from_bytes(b"\xff.txt"));
+            assert!(manifest_path(path).is_err());
+        }
+
+        app.rerun_manifest(read);
+        wait_batch(&mut app);
+        assert!(app.error_dialog.is_none());
+        let manifest = app.last_batch.take().unwrap();
+        assert_eq!(needs_rerun(&manifest), [false, false, false]);
+        let app_tab = with_separator(App::default(), "\t");
+        assert_eq!(
+            fs::read_to_string(out_dir.join("1.txt")).unwrap(),
+            run_output(&app_tab, Operation::Segment, "测试案例") + "\n",
+        );
+        fs::write(&in_paths[2], "分词案例").unwrap();
+        assert_eq!(needs_rerun(&manifest), [false, false, true]);
+
+        assert_eq!(
+            app.dicts.selected_dict().fingerprint(),
+            manifest.fingerprint
+        );
+        app.word = String::from("分词案例");
+        app.add_word();
+        assert_ne!(
+            app.dicts.selected_dict().fingerprint(),
+            manifest.fingerprint
+        );
+    }
+
+    #[test]
+    fn preview() {
+        let mut app = App {
+            preview_lines: 2,
+            ..Default::default()
+        };
+        let dir = TempDir::new("preview");
+        let in_paths = vec![dir.join("0.txt"), dir.join("1.txt"), dir.join("2.txt")];
+        fs::write(&in_paths[0], "分词测试\n测试案例\n案例").unwrap();
+        fs::write(&in_paths[2], "案例".repeat(MAX_PREVIEW_LEN)).unwrap();
+        app.start_batch(Operation::Segment, in_paths.clone());
+        assert!(app.batch_mode);
+        assert!(app.batch_preview.is_none());
+        wait_preview(&mut app);
+        let preview = app.batch_preview.as_ref().unwrap();
+        assert_eq!(preview.in_paths, in_paths);
+        let tokens = app
+            .segmenter(Operation::Segment)
+            .run_tokens(Operation::Segment, "分词测试\n测试案例");
+        let layout = app.get_layout(Operation::Segment);
+        let expected = CombineFormat::Text.render("0.txt", &tokens, &layout);
+        assert!(preview.output.starts_with(&expected));
+        assert!(preview.output[expected.len()..].starts_with("==> 1.txt <==\n"));
+        assert_eq!(preview.rows.last().unwrap().end, preview.output.len());
+        // Only whole characters within the limit are previewed.
+        let (_, long) = preview.output.split_once("==> 2.txt <==\n").unwrap();
+        let chars = MAX_PREVIEW_LEN / '案'.len_utf8();
+        assert_eq!(long.matches(['案', '例']).count(), chars);
+        app.settings_mut(Operation::Segment).separator = String::from("\t");
+        let preview = app.batch_preview.as_ref().unwrap();
+        assert_ne!(preview.manifest.settings.separator, "\t");
+    }
+
+    #[test]
+    fn hmm() {
+        let model = "#prob_start\n\
+            -0.5 -3.14e100 -3.14e100 -1\n\
+            #prob_trans\n\
+            -3.14e100 -0.1 -2.3 -3.14e100\n\
+            -0.7 -3.14e100 -3.14e100 -0.7\n\
+            -3.14e100 -0.7 -0.7 -3.14e100\n\
+            -0.7 -3.14e100 -3.14e100 -0.7\n\
+            #prob_emit\n\
+            甲:0,丙:0\n\
+            乙:0,丁:0\n\
+            \n\
+            戊:-1\n\n";
+        let hmm = Hmm::read("test.model", &mut model.as_bytes()).unwrap();
+        assert!(hmm.emit[2].is_empty());
+        let text = hmm.to_text();
+        assert_eq!(Hmm::read("", &mut text.as_bytes()).unwrap().to_text(), text);
+        let invalid = model.replace("-0.1", "0.1");
+        assert!(Hmm::read("", &mut invalid.as_bytes()).is_err());
+        let incomplete = model.replace("\n\n戊:-1\n\n", "\n");
+        assert!(Hmm::read("", &mut incomplete.as_bytes()).is_err());
+
+        let mut dict = Dict::new(DictName::Custom(String::from("test")), Words::new());
+        dict.add_word("丙丁", Some(10), Some("n"));
+        dict.add_word("其他", Some(1000), None);
+        dict.hmm = Some(sync::Arc::new(hmm));
+        let segmenter = Segmenter {
+            jieba: &dict.jieba,
+            hmm: dict.hmm.as_deref(),
+            use_hmm: true,
+        };
+        let words = |tokens: Vec<Token>| -> Vec<String> {
+            tokens.into_iter().map(|token| token.word).collect()
+        };
+        assert_eq!(
+            words(segmenter.segment_tokens("甲乙，丙丁")),
+            ["甲乙", "，", "丙丁"]
+        );
+        let tokens = segmenter.segment_tokens("戊甲乙");
+        assert_eq!(tokens[1].input, 1..3);
+        assert_eq!(words(tokens), ["戊", "甲乙"]);
+        let tags: Vec<_> = segmenter
+            .tag_tokens("甲乙丙丁")
+            .into_iter()
+            .map(|token| token.tag)
+            .collect();
+        assert_eq!(tags, ["x", "n"]);
+        let segmenter = Segmenter {
+            use_hmm: false,
+            ..segmenter
+        };
+        assert_eq!(words(segmenter.segment_tokens("甲乙")), ["甲", "乙"]);
+
+        let mut app = App::default();
+        let fingerprint = app.dicts.selected_dict().fingerprint();
+        app.dicts.load_hmm(
+            0,
+            "history.load-hmm",
+            Hmm::read("test.model", &mut model.as_bytes()).unwrap(),
+        );
+        assert!(app.dicts.can_reset_hmm());
+        assert_ne!(app.dicts.selected_dict().fingerprint(), fingerprint);
+        app.dicts.reset_hmm();
+        assert!(!app.dicts.can_reset_hmm());
+        app.dicts.undo();
+        assert_eq!(
+            app.dicts.selected_dict().hmm.as_ref().unwrap().name,
+            "test.model"
+        );
+    }
+
+    #[test]
+    fn train() {
+        let corpus = "甲乙 丙\n丙 甲乙 abc 丁戊己\n\n";
+        let hmm = Hmm::train("corpus.txt", &mut corpus.as_bytes()).unwrap();
+        let ln = f64::ln;
+        let assert_near = |actual: &[f64], expected: &[f64]| {
+            assert_eq!(actual.len(), expected.len());
+            for (actual, expected) in iter::zip(actual, expected) {
+                assert!((actual - expected).abs() < 1e-9, "{actual} != {expected}");
+            }
+        };
+        assert_near(
+            &hmm.start,
+            &[ln(2.0 / 3.0), MIN_LOG_PROB, MIN_LOG_PROB, ln(1.0 / 3.0)],
+        );
+        assert_near(&[hmm.trans[HMM_BEGIN][HMM_END]], &[ln(2.0 / 3.0)]);
+        assert_near(&[hmm.trans[HMM_BEGIN][HMM_MIDDLE]], &[ln(1.0 / 3.0)]);
+        assert_near(
+            &hmm.trans[HMM_END],
+            &[MIN_LOG_PROB, MIN_LOG_PROB, MIN_LOG_PROB, 0.0],
+        );
+        assert_near(&[hmm.trans[HMM_SINGLE][HMM_BEGIN]], &[0.0]);
+        assert_near(&[hmm.emit[HMM_BEGIN][&'甲']], &[ln(2.0 / 3.0)]);
+        assert_near(&[hmm.emit[HMM_SINGLE][&'丙']], &[0.0]);
+        assert!(!hmm.emit[HMM_SINGLE].contains_key(&'a'));
+        let text = hmm.to_text();
+        assert_eq!(Hmm::read("", &mut text.as_bytes()).unwrap().to_text(), text);
+        assert_eq!(hmm.cut("丙甲乙丁戊己"), ["丙", "甲乙", "丁戊己"]);
+        assert!(Hmm::train("", &mut "abc def\n".as_bytes()).is_err());
+        let tagged = "甲乙/n 丙/v\n丙/v [甲乙/n abc/x]nt 丁戊己/nr\n\n";
+        let tagged = Hmm::train("corpus.txt", &mut tagged.as_bytes()).unwrap();
+        assert_eq!(tagged.to_text(), text);
+
+        let mut app = App::default();
+        let (sender, receiver) = mpsc::channel();
+        app.training_hmm = Some((app.dicts.ids[app.dicts.idx], receiver));
+        app.open_error("copy.what", "".into());
+        sender.send(Err(String::from("no words"))).unwrap();
+        app.poll_training_hmm();
+        assert!(app.training_hmm.is_none());
+        assert_eq!(app.error_dialog.as_ref().unwrap().what, "copy.what");
+        app.close_error();
+        assert_eq!(app.error_dialog.take().unwrap().what, "train-hmm.what");
+    }
 }
@@ -0,0 +1,355 @@
+// Projects and preferences, saved as records of tab-separated fields.
+use crate::batch::{CombineFormat, DEFAULT_PREVIEW_LINES, MAX_PREVIEW_LINES, MAX_WORKERS};
+use crate::hmm::Hmm;
+use crate::settings::{Preset, Settings};
+use crate::{
+    App, Command, Dict, DictName, Dicts, Doc, Docs, Embedded, Entry, Layer, OPERATIONS, Operation,
+    Result, Slot, Words, can_bind, parse_shortcut, with_pick_file, with_save_file,
+};
+use rust_i18n::t;
+use std::io::BufRead as _;
+use std::{borrow, collections, error, fmt, fs, io, mem, sync};
+
+// State while reading a project file, checked by `finish`.
+#[derive(Default)]
+struct ProjectReader {
+    settings: [Settings; OPERATIONS.len()],
+    presets: Vec<Preset>,
+    combine_format: Option<CombineFormat>,
+    preview_lines: usize,
+    dicts: Vec<Slot>,
+    ids: Vec<usize>,
+    dict_idx: usize,
+    docs: Vec<Doc>,
+    doc_idx: usize,
+}
+
+#[derive(Debug)]
+enum ProjectError {
+    InvalidLine(usize),
+    Inconsistent,
+}
+
+impl App {
+    pub(crate) fn open_project(&mut self) {
+        if let Err(err) = with_pick_file(|path| {
+            let file = fs::File::open(path)?;
+            self.read_project(&mut io::BufReader::new(file))
+        }) {
+            self.open_error("open-project.what", err);
+        }
+    }
+
+    pub(crate) fn save_project(&mut self) {
+        if let Err(err) = with_save_file(|path| {
+            let file = fs::File::create(path)?;
+            self.write_project(&mut io::BufWriter::new(file))?;
+            Ok(())
+        }) {
+            self.open_error("save-project.what", err);
+        }
+    }
+
+    // Nothing is replaced unless the whole project is valid.
+    pub(crate) fn read_project(&mut self, buf: &mut impl io::BufRead) -> Result<()> {
+        // Projects from before the preview lack its record.
+        let mut reader = ProjectReader {
+            preview_lines: DEFAULT_PREVIEW_LINES,
+            ..Default::default()
+        };
+        let mut has_header = false;
+        for (idx, line) in buf.lines().enumerate() {
+            let line = line?;
+            let valid = split_record(&line).is_some_and(|record| {
+                let record: Vec<_> = record.iter().map(String::as_str).collect();
+                if idx == 0 {
+                    has_header = record == [PROJECT_HEADER, PROJECT_VERSION];
+                    has_header
+                } else {
+                    reader.read_record(&record).is_some()
+                }
+            });
+            if !valid {
+                return Err(Box::new(ProjectError::InvalidLine(idx + 1)));
+            }
+        }
+        if !has_header {
+            return Err(Box::new(ProjectError::InvalidLine(1)));
+        }
+        let settings = mem::take(&mut reader.settings);
+        let presets = mem::take(&mut reader.presets);
+        let combine_format = reader.combine_format;
+        let preview_lines = reader.preview_lines;
+        let (dicts, docs) = reader.finish().ok_or(ProjectError::Inconsistent)?;
+        self.settings = settings;
+        self.presets = presets;
+        self.combine_format = combine_format;
+        self.preview_lines = preview_lines;
+        self.dicts = dicts;
+        self.docs = docs;
+        self.select_doc(self.docs.idx);
+        Ok(())
+    }
+
+    // Preferences of this installation rather than of a project, kept in
+    // the storage of `eframe` as records.  Invalid records are skipped.
+    pub(crate) fn read_preferences(&mut self, preferences: &str) {
+        for line in preferences.lines() {
+            let Some(record) = split_record(line) else {
+                continue;
+            };
+            let record: Vec<_> = record.iter().map(String::as_str).collect();
+            match &*record {
+                ["workers", workers] => {
+                    if let Ok(workers @ 0..=MAX_WORKERS) = workers.parse() {
+                        self.workers = workers;
+                    }
+                }
+                ["shortcut", command, shortcut] => {
+                    let Some(command) = Command::from_key(command) else {
+                        continue;
+                    };
+                    if shortcut.is_empty() {
+                        self.shortcuts.set(command, None);
+                    } else if let Some(shortcut) =
+                        parse_shortcut(shortcut).filter(|&shortcut| can_bind(shortcut))
+                    {
+                        self.shortcuts.set(command, Some(shortcut));
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    pub(crate) fn write_preferences(&self) -> String {
+        let mut buf = Vec::new();
+        write_record(&mut buf, &["workers", &self.workers.to_string()])
+            .and_then(|()| self.shortcuts.write_preferences(&mut buf))
+            .expect("cannot be `Err(_)`; writing to memory never fails");
+        String::from_utf8(buf).expect("cannot be `Err(_)`; must have written UTF-8 records")
+    }
+
+    pub(crate) fn write_project(&self, buf: &mut impl io::Write) -> io::Result<()> {
+        write_record(buf, &[PROJECT_HEADER, PROJECT_VERSION])?;
+        for operation in OPERATIONS {
+            let settings = self.settings(operation);
+            settings.write_project(buf, &["settings", operation.to_key()])?;
+        }
+        for Preset { name, settings } in &self.presets {
+            settings.write_project(buf, &["preset", name])?;
+        }
+        let combine_format = self.combine_format.map_or("", CombineFormat::to_key);
+        write_record(buf, &["combine-format", combine_format])?;
+        write_record(buf, &["preview-lines", &self.preview_lines.to_string()])?;
+        self.dicts.write_project(buf)?;
+        self.docs.write_project(buf)?;
+        buf.flush()
+    }
+}
+
+impl ProjectReader {
+    fn read_record(&mut self, record: &[&str]) -> Option<()> {
+        match record {
+            ["settings", operation, fields @ ..] => {
+                let operation = Operation::from_key(operation)?;
+                self.settings[operation as usize] = Settings::from_fields(fields)?;
+            }
+            ["preset", name, fields @ ..] => self.presets.push(Preset {
+                name: String::from(*name),
+                settings: Settings::from_fields(fields)?,
+            }),
+            ["combine-format", ""] => self.combine_format = None,
+            ["combine-format", format] => {
+                self.combine_format = Some(CombineFormat::from_key(format)?);
+            }
+            ["preview-lines", lines] => {
+                self.preview_lines = lines.parse().ok()?;
+                if self.preview_lines > MAX_PREVIEW_LINES {
+                    return None;
+                }
+            }
+            ["unloaded", id, kind] => {
+                self.ids.push(id.parse().ok()?);
+                self.dicts.push(Slot::Unloaded(Embedded::from_key(kind)?));
+            }
+            ["dict", id, "embedded", kind] => {
+                self.push_dict(id, DictName::Embedded(Embedded::from_key(kind)?))?;
+            }
+            ["dict", id, "custom", name] => {
+                self.push_dict(id, DictName::Custom(String::from(*name)))?;
+            }
+            ["base", "embedded", kind] => {
+                self.last_dict()?.base = Embedded::from_key(kind)?.words();
+            }
+            ["base-word", word, freq, tag] => {
+                let base = sync::Arc::make_mut(&mut self.last_dict()?.base);
+                insert_record(base, word, freq, tag)?;
+            }
+            ["layer", name, enabled] => {
+                let enabled = enabled.parse().ok()?;
+                self.last_dict()?.layers.push(Layer {
+                    name: String::from(*name),
+                    words: Words::new(),
+                    enabled,
+                });
+            }
+            ["layer-word", word, freq, tag] => {
+                let layer = self.last_dict()?.layers.last_mut()?;
+                insert_record(&mut layer.words, word, freq, tag)?;
+            }
+            ["word", word, freq, tag] => {
+                insert_record(&mut self.last_dict()?.words, word, freq, tag)?;
+            }
+            ["disabled", word] => {
+                self.last_dict()?.disabled.insert(String::from(*word));
+            }
+            ["hmm", name, model] => {
+                let hmm = Hmm::read(*name, &mut model.as_bytes()).ok()?;
+                self.last_dict()?.hmm = Some(sync::Arc::new(hmm));
+            }
+            ["selected-dict", idx] => self.dict_idx = idx.parse().ok()?,
+            // The items are not saved, so that the output is stale as if
+            // the input was edited.
+            ["doc", dict, operation, name @ ..] if name.len() <= 1 => self.docs.push(Doc {
+                name: name.first().map(|name| String::from(*name)),
+                dict: dict.parse().ok()?,
+                operation: Operation::from_key(operation)?,
+                edited: true,
+                ..Default::default()
+            }),
+            ["input", input] => self.docs.last_mut()?.input = String::from(*input),
+            ["output", output] => self.docs.last_mut()?.output = String::from(*output),
+            ["selected-doc", idx] => self.doc_idx = idx.parse().ok()?,
+            _ => return None,
+        }
+        Some(())
+    }
+
+    fn push_dict(&mut self, id: &str, name: DictName) -> Option<()> {
+        self.ids.push(id.parse().ok()?);
+        self.dicts.push(Slot::Loaded(Dict::new(name, Words::new())));
+        Some(())
+    }
+
+    fn last_dict(&mut self) -> Option<&mut Dict> {
+        self.dicts.last_mut()?.loaded_mut()
+    }
+
+    // Establish the invariants of `Dicts`, `Dict` and `Docs`.
+    fn finish(self) -> Option<(Dicts, Docs)> {
+        let Self {
+            mut dicts,
+            ids,
+            dict_idx,
+            docs,
+            doc_idx,
+            ..
+        } = self;
+        let unique: collections::BTreeSet<_> = ids.iter().collect();
+        if dict_idx >= dicts.len() || unique.len() != ids.len() || doc_idx >= docs.len() {
+            return None;
+        }
+        for slot in &mut dicts {
+            if let Slot::Loaded(dict) = slot {
+                let disabled = mem::take(&mut dict.disabled);
+                dict.disabled = disabled
+                    .into_iter()
+                    .filter(|word| dict.has_word(word))
+                    .collect();
+                dict.rebuild();
+            }
+        }
+        let next_id = ids.iter().max().map_or(0, |id| id + 1);
+        let mut dicts = Dicts {
+            idx: dict_idx,
+            pending: None,
+            slots: dicts,
+            ids,
+            next_id,
+            history: collections::VecDeque::new(),
+            future: Vec::new(),
+        };
+        dicts.load_now(dict_idx);
+        Some((dicts, Docs { idx: doc_idx, docs }))
+    }
+}
+
+impl fmt::Display for ProjectError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let text = match self {
+            Self::InvalidLine(line) => t!("project.invalid-line", line = line),
+            Self::Inconsistent => t!("project.inconsistent"),
+        };
+        f.write_str(&text)
+    }
+}
+
+impl error::Error for ProjectError {}
+
+pub(crate) const PREFERENCES_KEY: &str = "chissor-preferences";
+const PROJECT_HEADER: &str = "chissor-project";
+const PROJECT_VERSION: &str = "2";
+
+// A project file is a sequence of records, one per line, whose fields
+// are separated by tabs, with backslash escapes for backslashes, tabs
+// and line breaks.
+pub(crate) fn write_record(buf: &mut impl io::Write, fields: &[&str]) -> io::Result<()> {
+    let fields: Vec<_> = fields.iter().map(|field| escape_field(field)).collect();
+    writeln!(buf, "{record}", record = fields.join("\t"))
+}
+
+pub(crate) fn write_entries(buf: &mut impl io::Write, key: &str, words: &Words) -> io::Result<()> {
+    for (word, Entry { freq, tag }) in words {
+        write_record(buf, &[key, word, &freq.to_string(), tag])?;
+    }
+    Ok(())
+}
+
+pub(crate) fn split_record(line: &str) -> Option<Vec<String>> {
+    line.split('\t').map(unescape_field).collect()
+}
+
+pub(crate) fn escape_field(field: &str) -> borrow::Cow<'_, str> {
+    if !field.contains(['\\', '\t', '\n', '\r']) {
+        return borrow::Cow::Borrowed(field);
+    }
+    let mut escaped = String::with_capacity(field.len());
+    for c in field.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '\t' => escaped.push_str("\\t"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            c => escaped.push(c),
+        }
+    }
+    borrow::Cow::Owned(escaped)
+}
+
+fn unescape_field(field: &str) -> Option<String> {
+    let mut unescaped = String::with_capacity(field.len());
+    let mut chars = field.chars();
+    while let Some(c) = chars.next() {
+        unescaped.push(match c {
+            '\\' => match chars.next()? {
+                '\\' => '\\',
+                't' => '\t',
+                'n' => '\n',
+                'r' => '\r',
+                _ => return None,
+            },
+            c => c,
+        });
+    }
+    Some(unescaped)
+}
+
+fn insert_record(words: &mut Words, word: &str, freq: &str, tag: &str) -> Option<()> {
+    let entry = Entry {
+        freq: freq.parse().ok()?,
+        tag: String::from(tag),
+    };
+    words.insert(String::from(word), entry);
+    Some(())
+}
@@ -0,0 +1,291 @@
+// A local HTTP server that segments text by the selected dictionary.
+use crate::{App, Dict, OPERATIONS, Operation, Result, Segmenter};
+use jieba_rs as jieba;
+use std::io::{BufRead as _, Read as _, Write as _};
+use std::sync::mpsc;
+use std::{fs, io, net, num, sync, thread, time};
+
+pub(crate) struct Server {
+    pub(crate) dict: Dict,
+    // Whether to use HMM by default, by operation.
+    pub(crate) use_hmm: [bool; OPERATIONS.len()],
+}
+
+const ENDPOINTS: [Endpoint; 5] = [
+    Endpoint::Cut,
+    Endpoint::CutForSearch,
+    Endpoint::CutAll,
+    Endpoint::Tag,
+    Endpoint::Keywords,
+];
+#[derive(Copy, Clone)]
+pub(crate) enum Endpoint {
+    Cut,
+    CutForSearch,
+    CutAll,
+    Tag,
+    Keywords,
+}
+
+// Body of a request to any endpoint, where only `text` is required.
+pub(crate) struct Request {
+    pub(crate) text: String,
+    pub(crate) use_hmm: bool,
+    pub(crate) top_k: usize,
+    pub(crate) allowed_pos: Vec<String>,
+}
+
+impl Server {
+    // A fixed number of workers take the connections in turn, so that
+    // more connections only wait in the queue.
+    fn serve(&self, listener: &net::TcpListener) {
+        let workers = thread::available_parallelism().map_or(1, num::NonZero::get);
+        let (sender, receiver) = mpsc::sync_channel::<net::TcpStream>(workers);
+        let receiver = sync::Mutex::new(receiver);
+        thread::scope(|scope| {
+            for _ in 0..workers {
+                scope.spawn(|| {
+                    loop {
+                        let stream = receiver
+                            .lock()
+                            .expect("cannot be `Err(_)`; must not panic while waiting")
+                            .recv();
+                        let Ok(stream) = stream else {
+                            break;
+                        };
+                        if let Err(err) = self.handle(&stream) {
+                            eprintln!("Connection failed: {err}");
+                        }
+                    }
+                });
+            }
+            for stream in listener.incoming() {
+                match stream {
+                    Ok(stream) => {
+                        if sender.send(stream).is_err() {
+                            break;
+                        }
+                    }
+                    Err(err) => eprintln!("Connection failed: {err}"),
+                }
+            }
+        });
+    }
+
+    // Only as much of HTTP/1.1 as needed by local clients: one request
+    // per connection, with the body delimited by `Content-Length`.
+    // Slow clients time out, and the request line and headers together
+    // must fit in `MAX_HEAD_LEN`.
+    pub(crate) fn handle(&self, stream: &net::TcpStream) -> io::Result<()> {
+        stream.set_read_timeout(Some(SERVE_TIMEOUT))?;
+        stream.set_write_timeout(Some(SERVE_TIMEOUT))?;
+        let mut reader = io::BufReader::new(stream);
+        let mut head = (&mut reader).take(MAX_HEAD_LEN as u64);
+        let mut request_line = String::new();
+        head.read_line(&mut request_line)?;
+        let mut content_length = 0;
+        loop {
+            let mut header = String::new();
+            if head.read_line(&mut header)? == 0 || header.trim_end().is_empty() {
+                break;
+            }
+            if let Some((name, value)) = header.split_once(':')
+                && name.eq_ignore_ascii_case("content-length")
+            {
+                content_length = value
+                    .trim()
+                    .parse()
+                    .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+            }
+        }
+        let head_too_large = head.limit() == 0;
+        let mut parts = request_line.split_whitespace();
+        let (status, body) = match (parts.next(), parts.next()) {
+            _ if head_too_large => (431, error_json("request header too large")),
+            _ if content_length > MAX_BODY_LEN => (413, error_json("request body too large")),
+            (Some("POST"), Some(path)) => {
+                let mut body = vec![0; content_length];
+                reader.read_exact(&mut body)?;
+                self.respond(path, &body)
+            }
+            (Some(_), Some(_)) => (405, error_json("method not allowed")),
+            _ => (400, error_json("malformed request")),
+        };
+        let reason = match status {
+            200 => "OK",
+            400 => "Bad Request",
+            404 => "Not Found",
+            405 => "Method Not Allowed",
+            413 => "Payload Too Large",
+            _ => "Request Header Fields Too Large",
+        };
+        let mut writer = stream;
+        write!(
+            writer,
+            "HTTP/1.1 {status} {reason}\r\n\
+             Content-Type: application/json; charset=utf-8\r\n\
+             Content-Length: {len}\r\n\
+             Connection: close\r\n\r\n\
+             {body}",
+            len = body.len(),
+        )?;
+        writer.flush()
+    }
+
+    fn respond(&self, path: &str, body: &[u8]) -> (u16, String) {
+        let Some(endpoint) = ENDPOINTS
+            .into_iter()
+            .find(|endpoint| endpoint.to_path() == path)
+        else {
+            return (404, error_json("unknown endpoint"));
+        };
+        let Some(request) = self.parse_request(endpoint, body) else {
+            return (400, error_json("invalid request body"));
+        };
+        (200, self.run(endpoint, &request))
+    }
+
+    pub(crate) fn parse_request(&self, endpoint: Endpoint, body: &[u8]) -> Option<Request> {
+        let body: serde_json::Value = serde_json::from_slice(body).ok()?;
+        let text = body.get("text")?.as_str()?;
+        let use_hmm = match body.get("hmm") {
+            None => self.use_hmm[endpoint.to_operation() as usize],
+            // Neither cutting all words nor jieba's TextRank for keywords
+            // uses HMM, whatever is asked.
+            Some(_) if matches!(endpoint, Endpoint::CutAll | Endpoint::Keywords) => return None,
+            Some(use_hmm) => use_hmm.as_bool()?,
+        };
+        let top_k = match body.get("top_k") {
+            None => DEFAULT_TOP_K,
+            Some(top_k) => usize::try_from(top_k.as_u64()?).ok()?,
+        };
+        let allowed_pos = match body.get("allowed_pos") {
+            None => Vec::new(),
+            Some(tags) => tags
+                .as_array()?
+                .iter()
+                .map(|tag| tag.as_str().map(String::from))
+                .collect::<Option<_>>()?,
+        };
+        Some(Request {
+            text: String::from(text),
+            use_hmm,
+            top_k,
+            allowed_pos,
+        })
+    }
+
+    fn run(&self, endpoint: Endpoint, request: &Request) -> String {
+        use jieba::KeywordExtract as _;
+        let jieba = &self.dict.jieba;
+        let Request {
+            text,
+            use_hmm,
+            top_k,
+            allowed_pos,
+        } = request;
+        let segmenter = Segmenter {
+            jieba,
+            hmm: self.dict.hmm.as_deref(),
+            use_hmm: *use_hmm,
+        };
+        let items: Vec<_> = match endpoint {
+            Endpoint::Cut => tokens_json(segmenter.cut(text)),
+            Endpoint::CutForSearch => tokens_json(segmenter.cut_for_search(text)),
+            Endpoint::CutAll => tokens_json(jieba.cut_all(text)),
+            Endpoint::Tag => segmenter
+                .tag(text)
+                .into_iter()
+                .map(|(word, tag)| serde_json::json!({"word": word, "tag": tag}))
+                .collect(),
+            // A weight that is not finite is written as `null`.
+            Endpoint::Keywords => jieba::TextRank::default()
+                .extract_keywords(jieba, text, *top_k, allowed_pos.clone())
+                .into_iter()
+                .map(|jieba::Keyword { keyword, weight }| {
+                    serde_json::json!({"keyword": keyword, "weight": weight})
+                })
+                .collect(),
+        };
+        serde_json::json!({ endpoint.to_key(): items }).to_string()
+    }
+}
+
+impl Endpoint {
+    fn to_path(self) -> &'static str {
+        match self {
+            Self::Cut => "/cut",
+            Self::CutForSearch => "/cut-for-search",
+            Self::CutAll => "/cut-all",
+            Self::Tag => "/tag",
+            Self::Keywords => "/keywords",
+        }
+    }
+
+    fn to_key(self) -> &'static str {
+        match self {
+            Self::Cut | Self::CutForSearch | Self::CutAll => "tokens",
+            Self::Tag => "tags",
+            Self::Keywords => "keywords",
+        }
+    }
+
+    // The operation whose settings apply to the endpoint.
+    fn to_operation(self) -> Operation {
+        match self {
+            Self::Cut | Self::Keywords => Operation::Segment,
+            Self::CutForSearch => Operation::SegmentGranular,
+            Self::CutAll => Operation::Search,
+            Self::Tag => Operation::Tag,
+        }
+    }
+}
+
+const SERVE_USAGE: &str = "Usage: chissor serve [--port <port>] [--project <file>] [--hmm]";
+const DEFAULT_PORT: u16 = 8080;
+// Serve the dictionary and options selected in the project, if any,
+// with HMM as set for the operation of each endpoint.
+pub(crate) fn serve(mut args: impl Iterator<Item = String>) -> Result<()> {
+    let mut port = DEFAULT_PORT;
+    let mut project = None;
+    let mut use_hmm = false;
+    while let Some(arg) = args.next() {
+        match &*arg {
+            "--port" => port = args.next().ok_or(SERVE_USAGE)?.parse()?,
+            "--project" => project = Some(args.next().ok_or(SERVE_USAGE)?),
+            "--hmm" => use_hmm = true,
+            _ => return Err(SERVE_USAGE.into()),
+        }
+    }
+    let mut app = App::default();
+    if let Some(project) = project {
+        let file = fs::File::open(project)?;
+        app.read_project(&mut io::BufReader::new(file))?;
+        app.dicts.wait_pending();
+    }
+    let server = Server {
+        dict: app.dicts.selected_dict().clone(),
+        use_hmm: OPERATIONS.map(|operation| use_hmm || app.settings(operation).use_hmm),
+    };
+    let listener = net::TcpListener::bind((net::Ipv4Addr::LOCALHOST, port))?;
+    eprintln!("Serving on http://{addr}", addr = listener.local_addr()?);
+    server.serve(&listener);
+    Ok(())
+}
+
+pub(crate) const MAX_HEAD_LEN: usize = 8 * 1024;
+const MAX_BODY_LEN: usize = 16 * 1024 * 1024;
+const SERVE_TIMEOUT: time::Duration = time::Duration::from_secs(10);
+const DEFAULT_TOP_K: usize = 20;
+fn tokens_json(tokens: Vec<jieba::Token>) -> Vec<serde_json::Value> {
+    tokens
+        .into_iter()
+        .map(|jieba::Token { word, start, end }| {
+            serde_json::json!({"word": word, "start": start, "end": end})
+        })
+        .collect()
+}
+
+fn error_json(message: &str) -> String {
+    serde_json::json!({ "error": message }).to_string()
+}
@@ -0,0 +1,358 @@
+// Output settings of each operation, their presets, and the layout of
+// the results they make.
+use crate::project::write_record;
+use crate::{App, Item, OPERATIONS, Operation, Token, count_line_breaks, or_default};
+use eframe::egui;
+use rust_i18n::t;
+use std::{borrow, io};
+
+// Output settings of a run, with the defaults filled in and the escapes
+// replaced.
+#[derive(Clone)]
+pub(crate) struct Layout<'a> {
+    pub(crate) template: borrow::Cow<'a, str>,
+    pub(crate) separator: borrow::Cow<'a, str>,
+    pub(crate) line_separator: borrow::Cow<'a, str>,
+}
+
+const QUICK_SEPARATORS: [QuickSeparator; 5] = [
+    QuickSeparator::Space,
+    QuickSeparator::Tab,
+    QuickSeparator::Slash,
+    QuickSeparator::Newline,
+    QuickSeparator::IdeographicSpace,
+];
+#[derive(Copy, Clone, PartialEq)]
+pub(crate) enum QuickSeparator {
+    Space,
+    Tab,
+    Slash,
+    Newline,
+    IdeographicSpace,
+}
+
+// Output settings of an operation, where empty strings fall back to
+// the defaults.
+#[derive(Default, Clone, PartialEq)]
+pub(crate) struct Settings {
+    pub(crate) template: String,
+    pub(crate) separator: String,
+    pub(crate) line_separator: String,
+    pub(crate) use_hmm: bool,
+}
+
+// Settings saved by name, to be applied to any operation.
+pub(crate) struct Preset {
+    pub(crate) name: String,
+    pub(crate) settings: Settings,
+}
+
+impl App {
+    pub(crate) fn show_settings(&mut self, ui: &mut egui::Ui) {
+        egui::ComboBox::from_id_salt("settings operation")
+            .selected_text(self.settings_operation.to_text())
+            .show_ui(ui, |ui| {
+                for operation in OPERATIONS {
+                    let text = operation.to_text();
+                    ui.selectable_value(&mut self.settings_operation, operation, text);
+                }
+            })
+            .response
+            .on_hover_text(t!("settings-operation.hover"));
+        let settings = self.settings_mut(self.settings_operation);
+        ui.add(egui::TextEdit::singleline(&mut settings.template).hint_text(t!("template.text")))
+            .on_hover_text(t!("template.hover"));
+        ui.horizontal(|ui| {
+            ui.add(
+                egui::TextEdit::singleline(&mut settings.separator).hint_text(t!("separator.text")),
+            )
+            .on_hover_text(t!("separator.hover"));
+            show_quick_separators(ui, &mut settings.separator);
+        });
+        ui.horizontal(|ui| {
+            ui.add(
+                egui::TextEdit::singleline(&mut settings.line_separator)
+                    .hint_text(t!("line-separator.text")),
+            )
+            .on_hover_text(t!("line-separator.hover"));
+            show_quick_separators(ui, &mut settings.line_separator);
+        });
+        ui.checkbox(&mut settings.use_hmm, t!("use-hmm.text"))
+            .on_hover_text(t!("use-hmm.hover"));
+        ui.horizontal(|ui| {
+            ui.add(
+                egui::TextEdit::singleline(&mut self.preset_name).hint_text(t!("preset-name.text")),
+            )
+            .on_hover_text(t!("preset-name.hover"));
+            if ui
+                .add_enabled(
+                    self.can_save_preset(),
+                    egui::Button::new(t!("save-preset.text")),
+                )
+                .on_hover_text(t!("save-preset.hover"))
+                .clicked()
+            {
+                self.save_preset();
+            }
+        });
+        ui.add_enabled_ui(!self.presets.is_empty(), |ui| {
+            ui.menu_button(t!("presets.text"), |ui| {
+                let (mut apply, mut delete) = (None, None);
+                for (idx, preset) in self.presets.iter().enumerate() {
+                    ui.horizontal(|ui| {
+                        if ui.button(&preset.name).clicked() {
+                            apply = Some(idx);
+                        }
+                        if ui
+                            .small_button(t!("delete-preset.text"))
+                            .on_hover_text(t!("delete-preset.hover"))
+                            .clicked()
+                        {
+                            delete = Some(idx);
+                        }
+                    });
+                }
+                if let Some(idx) = apply {
+                    self.apply_preset(idx);
+                } else if let Some(idx) = delete {
+                    self.presets.remove(idx);
+                }
+            })
+            .response
+            .on_hover_text(t!("presets.hover"));
+        });
+    }
+
+    pub(crate) fn settings(&self, operation: Operation) -> &Settings {
+        &self.settings[operation as usize]
+    }
+
+    pub(crate) fn settings_mut(&mut self, operation: Operation) -> &mut Settings {
+        &mut self.settings[operation as usize]
+    }
+
+    pub(crate) fn get_layout(&self, operation: Operation) -> Layout<'_> {
+        self.settings(operation).to_layout(operation)
+    }
+
+    pub(crate) fn can_save_preset(&self) -> bool {
+        !self.preset_name.is_empty()
+    }
+
+    // A preset of the same name is replaced.
+    pub(crate) fn save_preset(&mut self) {
+        assert!(
+            self.can_save_preset(),
+            "must not trigger this action without preset name",
+        );
+        let settings = self.settings(self.settings_operation).clone();
+        match self
+            .presets
+            .iter_mut()
+            .find(|preset| preset.name == self.preset_name)
+        {
+            Some(preset) => preset.settings = settings,
+            None => self.presets.push(Preset {
+                name: self.preset_name.clone(),
+                settings,
+            }),
+        }
+    }
+
+    pub(crate) fn apply_preset(&mut self, idx: usize) {
+        *self.settings_mut(self.settings_operation) = self.presets[idx].settings.clone();
+    }
+}
+
+impl Layout<'_> {
+    // Render `token`, the `index`th item, by the template, where `{word}`,
+    // `{tag}`, `{start}`, `{end}` and `{index}` are replaced and anything
+    // else is kept as is.
+    pub(crate) fn render(&self, token: &Token, index: usize) -> String {
+        let mut text = String::new();
+        let mut rest = &*self.template;
+        while let Some(start) = rest.find('{') {
+            text.push_str(&rest[..start]);
+            rest = &rest[start..];
+            let field = rest.find('}').and_then(|end| {
+                let value = match &rest[1..end] {
+                    "word" => borrow::Cow::Borrowed(&*token.word),
+                    "tag" => borrow::Cow::Borrowed(&*token.tag),
+                    "start" => borrow::Cow::Owned(token.input.start.to_string()),
+                    "end" => borrow::Cow::Owned(token.input.end.to_string()),
+                    "index" => borrow::Cow::Owned(index.to_string()),
+                    _ => return None,
+                };
+                Some((value, end))
+            });
+            if let Some((value, end)) = field {
+                text.push_str(&value);
+                rest = &rest[end + 1..];
+            } else {
+                text.push('{');
+                rest = &rest[1..];
+            }
+        }
+        text.push_str(rest);
+        text
+    }
+
+    // What joins two items with `breaks` line breaks between them.
+    pub(crate) fn to_separator(&self, breaks: usize) -> borrow::Cow<'_, str> {
+        if breaks == 0 {
+            borrow::Cow::Borrowed(&self.separator)
+        } else {
+            borrow::Cow::Owned(self.line_separator.repeat(breaks))
+        }
+    }
+
+    // Render `tokens` and join them, with the output ranges filled in.
+    // Line breaks are not items, but join the items around them with
+    // the line separator, once per break, instead of the separator.
+    pub(crate) fn join(&self, tokens: &[Token]) -> (String, Vec<Item>) {
+        let mut output = String::new();
+        let mut items = Vec::new();
+        let mut breaks = None;
+        let mut start = 0;
+        for token in tokens {
+            if let Some(count) = count_line_breaks(&token.word) {
+                breaks = breaks.map(|breaks| breaks + count);
+                continue;
+            }
+            if let Some(breaks) = breaks {
+                let separator = self.to_separator(breaks);
+                start += separator.chars().count();
+                output.push_str(&separator);
+            }
+            let text = self.render(token, items.len());
+            let end = start + text.chars().count();
+            output.push_str(&text);
+            items.push(Item {
+                text,
+                input: token.input.clone(),
+                output: start..end,
+            });
+            start = end;
+            breaks = Some(0);
+        }
+        (output, items)
+    }
+}
+
+impl QuickSeparator {
+    fn to_key(self) -> &'static str {
+        match self {
+            Self::Space => "space",
+            Self::Tab => "tab",
+            Self::Slash => "slash",
+            Self::Newline => "newline",
+            Self::IdeographicSpace => "ideographic-space",
+        }
+    }
+
+    fn to_name(self) -> String {
+        let key = format!("quick-separator.{key}.name", key = self.to_key());
+        String::from(t!(&key))
+    }
+
+    // The separator as typed, with escapes for what cannot be typed.
+    pub(crate) fn to_setting(self) -> &'static str {
+        match self {
+            Self::Space => " ",
+            Self::Tab => "\\t",
+            Self::Slash => " / ",
+            Self::Newline => "\\n",
+            Self::IdeographicSpace => "\\u{3000}",
+        }
+    }
+}
+
+impl Settings {
+    pub(crate) fn to_layout(&self, operation: Operation) -> Layout<'_> {
+        Layout {
+            template: unescape(or_default(&self.template, operation.to_template())),
+            separator: unescape(or_default(&self.separator, "\n")),
+            line_separator: unescape(or_default(&self.line_separator, "\n")),
+        }
+    }
+
+    pub(crate) fn from_fields(fields: &[&str]) -> Option<Self> {
+        let [template, separator, line_separator, use_hmm] = fields else {
+            return None;
+        };
+        Some(Settings {
+            template: String::from(*template),
+            separator: String::from(*separator),
+            line_separator: String::from(*line_separator),
+            use_hmm: use_hmm.parse().ok()?,
+        })
+    }
+
+    // Write the settings as the fields after `head`.
+    pub(crate) fn write_project(&self, buf: &mut impl io::Write, head: &[&str]) -> io::Result<()> {
+        let use_hmm = self.use_hmm.to_string();
+        let mut record = head.to_vec();
+        record.extend([
+            self.template.as_str(),
+            self.separator.as_str(),
+            self.line_separator.as_str(),
+            use_hmm.as_str(),
+        ]);
+        write_record(buf, &record)
+    }
+}
+
+// Show a menu to replace `separator` by a quick separator.
+fn show_quick_separators(ui: &mut egui::Ui, separator: &mut String) {
+    ui.menu_button(t!("quick-separator.text"), |ui| {
+        for quick in QUICK_SEPARATORS {
+            if ui.button(quick.to_name()).clicked() {
+                *separator = String::from(quick.to_setting());
+            }
+        }
+    })
+    .response
+    .on_hover_text(t!("quick-separator.hover"));
+}
+
+// Replace the escapes in a setting typed by the user, as in a field or
+// as `\u{3000}`, but keep a backslash that does not start one as is.
+pub(crate) fn unescape(setting: &str) -> borrow::Cow<'_, str> {
+    if !setting.contains('\\') {
+        return borrow::Cow::Borrowed(setting);
+    }
+    let mut unescaped = String::with_capacity(setting.len());
+    let mut rest = setting;
+    while let Some(start) = rest.find('\\') {
+        unescaped.push_str(&rest[..start]);
+        rest = &rest[start + 1..];
+        match read_escape(rest) {
+            Some((c, len)) => {
+                unescaped.push(c);
+                rest = &rest[len..];
+            }
+            None => unescaped.push('\\'),
+        }
+    }
+    unescaped.push_str(rest);
+    borrow::Cow::Owned(unescaped)
+}
+
+// Return the char escaped at the start of `rest`, right after a
+// backslash, and the length of the escape there.
+fn read_escape(rest: &str) -> Option<(char, usize)> {
+    let c = match rest.chars().next()? {
+        '\\' => '\\',
+        't' => '\t',
+        'n' => '\n',
+        'r' => '\r',
+        'u' => {
+            let hex = rest.strip_prefix("u{")?;
+            let end = hex.find('}')?;
+            let code = u32::from_str_radix(&hex[..end], 16).ok()?;
+            return Some((char::from_u32(code)?, end + 3));
+        }
+        _ => return None,
+    };
+    Some((c, 1))
+}